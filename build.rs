@@ -0,0 +1,81 @@
+use std::{env, process::Command};
+
+fn main() {
+    for cfg in ["static", "scalar", "sse2", "avx2", "avx512", "avx512vl", "avx512_nightly", "neon", "portable"] {
+        println!("cargo:rustc-check-cfg=cfg(cubehash_{cfg})");
+    }
+
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let x86 = arch == "x86" || arch == "x86_64";
+    let aarch64 = arch == "aarch64" && env::var("CARGO_CFG_TARGET_ENDIAN").is_ok_and(|e| e == "little");
+    let target_features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has = |f: &str| target_features.split(',').any(|t| t == f);
+    let selectable = env::var_os("CARGO_FEATURE_SELECTABLE_BACKEND").is_some();
+    let portable = env::var_os("CARGO_FEATURE_UNSTABLE_PORTABLE_SIMD").is_some();
+
+    // AVX-512 intrinsics and target features were stabilized in Rust 1.89. On
+    // older compilers the avx512 backend needs nightly and the unstable-avx512
+    // feature.
+    let stable = rustc_version().is_some_and(|(minor, prerelease)| minor > 89 || (minor == 89 && !prerelease));
+    let unstable = env::var_os("CARGO_FEATURE_UNSTABLE_AVX512").is_some();
+    let avx512 = x86 && (stable || unstable);
+
+    /*
+     * If the target features already guarantee a SIMD backend (e.g. built
+     * with -C target-feature=+avx2 or -C target-cpu=native), that backend is
+     * the only one compiled in and there's no runtime detection. Plain x86_64
+     * only guarantees SSE2, so it keeps detecting AVX2 and AVX-512 at runtime.
+     * Targets with no SIMD backend at all are static too. The selector needs
+     * every backend, so it turns this off. Soft is always compiled in so the
+     * default can be overridden onto it and the self-test can fall back to it.
+     */
+    let fixed = if selectable {
+        None
+    } else if avx512 && has("avx512f") {
+        // the AMD-only AVX512VL preference can't be known statically
+        Some("avx512")
+    } else if x86 && has("avx2") {
+        Some("avx2")
+    } else if aarch64 && has("neon") {
+        Some("neon")
+    } else if !x86 && !aarch64 {
+        Some(if portable { "portable" } else { "scalar" })
+    } else {
+        None
+    };
+
+    let backends: &[&str] = match fixed {
+        Some(backend) => {
+            println!("cargo:rustc-cfg=cubehash_static");
+            &[backend]
+        },
+        None if x86 && avx512 => &["scalar", "sse2", "avx2", "avx512", "avx512vl"],
+        None if x86 => &["scalar", "sse2", "avx2"],
+        None if aarch64 => &["scalar", "neon"],
+        None => &["scalar"]
+    };
+
+    for backend in backends {
+        println!("cargo:rustc-cfg=cubehash_{backend}");
+    }
+    if portable && fixed.is_none() {
+        println!("cargo:rustc-cfg=cubehash_portable");
+    }
+    if unstable && !stable && backends.contains(&"avx512") {
+        println!("cargo:rustc-cfg=cubehash_avx512_nightly");
+    }
+}
+
+// (minor version, whether it's a nightly/beta/dev build)
+fn rustc_version() -> Option<(u32, bool)> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    let version = version.split_whitespace().nth(1)?;
+    let (version, prerelease) = match version.split_once('-') {
+        Some((version, _)) => (version, true),
+        None => (version, false)
+    };
+    let minor = version.split('.').nth(1)?.parse().ok()?;
+    Some((minor, prerelease))
+}
@@ -6,6 +6,8 @@ fn square<T: std::ops::Mul<T> + Copy>(x: T) -> <T as std::ops::Mul<T>>::Output {
     x*x
 }
 
+// __cpuid is only unsafe on older compilers
+#[allow(unused_unsafe)]
 fn profile(gen: impl Digest + Clone) {
     static DATA: OnceLock<Vec<u8>> = OnceLock::new();
     let mut hsh = None;
@@ -42,19 +44,25 @@ fn profile(gen: impl Digest + Clone) {
 
 fn main() {
     // let soft = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Soft).unwrap());
+    let scalar = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Scalar).unwrap());
     let sse2 = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Sse2).unwrap());
     let avx2 = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Avx2).unwrap());
     let avx512 = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Avx512).unwrap());
+    let avx512vl = CubeHash512::from_core(CubeHashCore::new_with_backend(CubeHashBackend::Avx512Vl).unwrap());
     // let sha2 = sha2::Sha512::new();
 
     // print!("SHA-512:         ");
     // profile(sha2);
     // print!("cubehash/soft:   ");
     // profile(soft);
+    print!("cubehash/scalar: ");
+    profile(scalar);
     print!("cubehash/SSE2:   ");
     profile(sse2);
     print!("cubehash/AVX2:   ");
     profile(avx2);
     print!("cubehash/AVX512: ");
     profile(avx512);
+    print!("cubehash/AVX512VL: ");
+    profile(avx512vl);
 }
\ No newline at end of file
@@ -1,103 +1,252 @@
+use core::{fmt, marker::PhantomData, sync::atomic::{AtomicU8, Ordering}};
+
 use digest::{
-    array::{Array, ArraySize}, block_buffer::Eager, core_api::{
-        AlgorithmName, Block, BlockSizeUser, Buffer, BufferKindUser, FixedOutputCore, UpdateCore
-    }, typenum::{IsGreater, IsLessOrEqual, True, Unsigned, U0, U32, U64}, HashMarker, Output, OutputSizeUser
+    array::ArraySize, block_buffer::Eager, core_api::{
+        AlgorithmName, Block, BlockSizeUser, Buffer, BufferKindUser, ExtendableOutputCore, FixedOutputCore, TruncSide, UpdateCore, VariableOutputCore, XofReaderCore
+    }, crypto_common::{hazmat::{DeserializeStateError, SerializableState, SerializedState}, BlockSizes}, typenum::{IsGreater, IsLessOrEqual, True, Unsigned, U0, U64, U128, U256}, HashMarker, InvalidOutputSize, Output, OutputSizeUser, Reset
 };
 
+#[macro_use]
+mod multi;
+#[cfg(any(cubehash_sse2, cubehash_avx512vl))]
+#[macro_use]
+mod xmm;
 mod soft;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(cubehash_scalar)]
+mod scalar;
+#[cfg(cubehash_sse2)]
 mod sse2;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(cubehash_avx2)]
 mod avx2;
-#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
+#[cfg(cubehash_avx512)]
 mod avx512;
-#[cfg(target_arch = "aarch64")]
+#[cfg(cubehash_avx512vl)]
+mod avx512vl;
+#[cfg(cubehash_neon)]
 mod neon;
+#[cfg(cubehash_portable)]
+mod portable;
+#[cfg(feature = "autotune")]
+pub(crate) mod autotune;
+#[cfg(feature = "self-test")]
+pub(crate) mod selftest;
+#[cfg(any(test, feature = "self-test"))]
+pub(crate) mod kat;
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(cubehash_sse2)]
 cpufeatures::new!(cpu_sse2, "sse2");
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(cubehash_avx2)]
 cpufeatures::new!(cpu_avx2, "avx", "avx2");
-#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
+#[cfg(cubehash_avx512)]
 cpufeatures::new!(cpu_avx512, "avx", "avx512f");
-#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+#[cfg(cubehash_avx512vl)]
+cpufeatures::new!(cpu_avx512vl, "avx512f", "avx512vl");
+#[cfg(cubehash_neon)]
 cpufeatures::new!(cpu_neon, "neon");
 
-#[derive(Clone)]
-enum Backend<const I: u16, const R: u16, const F: u16, H> {
-    Soft(soft::Soft<I, R, F, H>),
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    Sse2(sse2::Sse2<I, R, F, H>),
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    Avx2(avx2::Avx2<I, R, F, H>),
-    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-    Avx512(avx512::Avx512<I, R, F, H>),
-    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-    Neon(neon::Neon<I, R, F, H>),
+#[cfg(cubehash_avx512vl)]
+#[allow(unused_unsafe)] // __cpuid is only unsafe on older compilers
+fn is_amd() -> bool {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::__cpuid;
+
+    // "AuthenticAMD"
+    let leaf = unsafe { __cpuid(0) };
+    (leaf.ebx, leaf.edx, leaf.ecx) == (0x68747541, 0x69746e65, 0x444d4163)
 }
 
 #[derive(Clone)]
-pub struct CubeHashCore<const I: u16, const R: u16, const F: u16, H>(Backend<I, R, F, H>);
+pub(crate) enum Backend {
+    Soft(soft::Soft),
+    #[cfg(cubehash_scalar)]
+    Scalar(scalar::Scalar),
+    #[cfg(cubehash_sse2)]
+    Sse2(sse2::Sse2),
+    #[cfg(cubehash_avx2)]
+    Avx2(avx2::Avx2),
+    #[cfg(cubehash_avx512)]
+    Avx512(avx512::Avx512),
+    #[cfg(cubehash_avx512vl)]
+    Avx512Vl(avx512vl::Avx512Vl),
+    #[cfg(cubehash_neon)]
+    Neon(neon::Neon),
+    #[cfg(cubehash_portable)]
+    Portable(portable::Portable),
+}
 
-trait CubeHashBackend<const I: u16, const R: u16, const F: u16, H> {
-    unsafe fn init() -> Self where H: Unsigned;
-    unsafe fn update_block(&mut self, block: &Array<u8, U32>);
-    unsafe fn finalize(&mut self, out: &mut Array<u8, H>) where H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>;
+pub(crate) use soft::{iv, digest as const_digest};
+
+trait CubeHashBackend {
+    unsafe fn init(iv: &[u32; 32]) -> Self;
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16);
+    unsafe fn finalize(&mut self, f: u16);
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16);
+    unsafe fn output(&self, out: &mut [u8]);
+
+    // Runs the rounds backwards; backends without their own inverse go
+    // through Soft
+    unsafe fn unpermute(&mut self, rounds: u16) where Self: Sized {
+        let mut buf = [0; 128];
+        self.output(&mut buf);
+        let mut soft = soft::Soft::init(&core::array::from_fn(|i| u32::from_le_bytes(buf[4*i..4*i+4].try_into().unwrap())));
+        soft.unpermute(rounds);
+        soft.output(&mut buf);
+        *self = Self::init(&core::array::from_fn(|i| u32::from_le_bytes(buf[4*i..4*i+4].try_into().unwrap())));
+    }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> HashMarker for CubeHashCore<I, R, F, H> {}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BackendSelector {
+    Soft,
+    Scalar,
+    Sse2,
+    Avx2,
+    Avx512,
+    Avx512Vl,
+    Neon,
+    Portable
+}
 
-impl<const I: u16, const R: u16, const F: u16, H: Unsigned> AlgorithmName for CubeHashCore<I, R, F, H> {
-    fn write_alg_name(f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "CubeHash<{}, {}, {}, {}>", I, R, F, H::USIZE)
+impl BackendSelector {
+    const ALL: [Self; 8] = [Self::Soft, Self::Scalar, Self::Sse2, Self::Avx2, Self::Avx512, Self::Avx512Vl, Self::Neon, Self::Portable];
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Soft => "soft",
+            Self::Scalar => "scalar",
+            Self::Sse2 => "sse2",
+            Self::Avx2 => "avx2",
+            Self::Avx512 => "avx512",
+            Self::Avx512Vl => "avx512vl",
+            Self::Neon => "neon",
+            Self::Portable => "portable"
+        }
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> BlockSizeUser for CubeHashCore<I, R, F, H> {
-    type BlockSize = U32;
+impl fmt::Display for BackendSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H: ArraySize> OutputSizeUser for CubeHashCore<I, R, F, H> {
-    type OutputSize = H;
+impl core::str::FromStr for BackendSelector {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|backend| s.eq_ignore_ascii_case(backend.name())).ok_or(BackendError::UnknownName)
+    }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> BufferKindUser for CubeHashCore<I, R, F, H> {
-    type BufferKind = Eager;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendError {
+    NotCompiledIn,
+    UnsupportedCpu,
+    UnknownName
 }
 
-#[cfg(feature = "selectable-backend")]
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum BackendSelector {
-    Soft,
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    Sse2,
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    Avx2,
-    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-    Avx512,
-    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-    Neon
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotCompiledIn => "backend was not compiled into this build",
+            Self::UnsupportedCpu => "backend is not supported by this CPU",
+            Self::UnknownName => "expected one of soft, scalar, sse2, avx2, avx512, avx512vl, neon or portable"
+        })
+    }
 }
 
-#[cfg(feature = "selectable-backend")]
-impl<const I: u16, const R: u16, const F: u16, H: Unsigned> CubeHashCore<I, R, F, H> {
-    pub fn new_with_backend(backend: BackendSelector) -> Option<Self> {
-        match backend {
-            BackendSelector::Soft => Some(Self(Backend::Soft(unsafe { soft::Soft::init() }))),
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            BackendSelector::Sse2 => cpu_sse2::get().then(|| Self(Backend::Sse2(unsafe { sse2::Sse2::init() }))),
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            BackendSelector::Avx2 => cpu_avx2::get().then(|| Self(Backend::Avx2(unsafe { avx2::Avx2::init() }))),
-            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-            BackendSelector::Avx512 => cpu_avx512::get().then(|| Self(Backend::Avx512(unsafe { avx512::Avx512::init() }))),
-            #[cfg(all(target_arch = "aarch64", target_endian = "little"))] 
-            BackendSelector::Neon => cpu_neon::get().then(|| Self(Backend::Neon(unsafe { neon::Neon::init() }))),
-        }
+#[cfg(feature = "std")]
+impl std::error::Error for BackendError {}
+
+// 0 is no override, otherwise the index into BackendSelector::ALL plus one
+static DEFAULT_OVERRIDE: AtomicU8 = AtomicU8::new(0);
+
+// CUBEHASH_BACKEND is read once, before the first override is looked at or
+// set. Holds the value and why it was rejected, if it was.
+#[cfg(feature = "std")]
+static ENV_OVERRIDE: std::sync::OnceLock<Option<(std::ffi::OsString, BackendError)>> = std::sync::OnceLock::new();
+
+#[cfg(feature = "std")]
+fn load_env_override() -> &'static Option<(std::ffi::OsString, BackendError)> {
+    ENV_OVERRIDE.get_or_init(|| {
+        let name = std::env::var_os("CUBEHASH_BACKEND").filter(|name| !name.is_empty())?;
+        let result = name.to_str().ok_or(BackendError::UnknownName).and_then(str::parse).and_then(|backend| store_override(Some(backend)));
+        result.err().map(|e| (name, e))
+    })
+}
+
+// The CUBEHASH_BACKEND value that was ignored and why, e.g. a backend that
+// isn't compiled in or can't run on this CPU
+#[cfg(feature = "std")]
+pub fn rejected_env_backend() -> Option<(&'static std::ffi::OsStr, BackendError)> {
+    load_env_override().as_ref().map(|(name, e)| (name.as_os_str(), *e))
+}
+
+// Makes Default (and everything built on it) use the given backend instead of
+// the detected one; None goes back to detection. Backends that can't run here
+// are rejected and leave the current setting alone. Builds with a static
+// backend can only switch between it and Soft.
+pub fn set_default_backend(backend: Option<BackendSelector>) -> Result<(), BackendError> {
+    #[cfg(feature = "std")]
+    load_env_override();
+
+    store_override(backend)
+}
+
+fn store_override(backend: Option<BackendSelector>) -> Result<(), BackendError> {
+    let value = match backend {
+        Some(backend) => {
+            Backend::check(backend)?;
+            backend.index() as u8 + 1
+        },
+        None => 0
+    };
+    DEFAULT_OVERRIDE.store(value, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn default_backend_override() -> Option<BackendSelector> {
+    #[cfg(feature = "std")]
+    load_env_override();
+
+    match DEFAULT_OVERRIDE.load(Ordering::Relaxed) {
+        0 => None,
+        n => Some(BackendSelector::ALL[n as usize - 1])
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H: Unsigned> Default for CubeHashCore<I, R, F, H> {
-    fn default() -> Self {
+// The backends new_with_backend would accept on this machine
+pub fn available_backends() -> impl Iterator<Item = BackendSelector> {
+    BackendSelector::ALL.into_iter().filter(|&backend| Backend::check(backend).is_ok())
+}
+
+impl Backend {
+    pub(crate) fn new(iv: &[u32; 32]) -> Self {
+        let backend = default_backend_override()
+            .and_then(|backend| Self::new_with_backend(backend, iv).ok())
+            .unwrap_or_else(|| Self::select(iv));
+
+        #[cfg(feature = "self-test")]
+        if backend.selector() != BackendSelector::Soft && selftest::self_test(backend.selector()) != Ok(true) {
+            return Self::Soft(unsafe { soft::Soft::init(iv) })
+        }
+
+        backend
+    }
+
+    #[cfg(not(cubehash_static))]
+    fn select(iv: &[u32; 32]) -> Self {
+        #[cfg(feature = "autotune")]
+        if let Ok(backend) = Self::new_with_backend(autotune::autotune().backend, iv) {
+            return backend
+        }
+
         cfg_if::cfg_if! {
             if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
                 /*
@@ -136,76 +285,519 @@ impl<const I: u16, const R: u16, const F: u16, H: Unsigned> Default for CubeHash
                  * icelake-server | Yes    | SSE2   | 4.3
                  * icelake-server | Yes    | AVX2   | 5.3
                  * icelake-server | Yes    | AVX512 | 3.9
+                 *
+                 * The AVX512VL backend is the SSE2 code with EVEX rotates, i.e. what
+                 * the native SSE2 rows get, without needing a native build. It's
+                 * picked over AVX512 on AMD only.
+                 *
+                 * Core           | Native | Set      | Cycles/byte
+                 * ---------------+--------+----------+------------
+                 * sapphirerapids | No     | SSE2     | 6.1
+                 * sapphirerapids | No     | AVX2     | 5.1
+                 * sapphirerapids | No     | AVX512   | 3.8
+                 * sapphirerapids | No     | AVX512VL | 4.4
                  */
 
-                #[cfg(feature = "unstable-avx512")]
+                #[cfg(cubehash_avx512vl)]
+                if cpu_avx512vl::get() && is_amd() {
+                    return Self::Avx512Vl(unsafe { avx512vl::Avx512Vl::init(iv) })
+                }
+
+                #[cfg(cubehash_avx512)]
                 if cpu_avx512::get() {
-                    return Self(Backend::Avx512(unsafe { avx512::Avx512::init() }))
+                    return Self::Avx512(unsafe { avx512::Avx512::init(iv) })
                 }
 
                 if cpu_avx2::get() {
-                    return Self(Backend::Avx2(unsafe { avx2::Avx2::init() }))
+                    return Self::Avx2(unsafe { avx2::Avx2::init(iv) })
                 }
 
                 if cpu_sse2::get() {
-                    return Self(Backend::Sse2(unsafe { sse2::Sse2::init() }))
+                    return Self::Sse2(unsafe { sse2::Sse2::init(iv) })
                 }
             } else if #[cfg(all(target_arch = "aarch64", target_endian = "little"))] {
                 if cpu_neon::get() {
-                    return Self(Backend::Neon(unsafe { neon::Neon::init() }))
+                    return Self::Neon(unsafe { neon::Neon::init(iv) })
                 }
             }
         }
 
-        Self(Backend::Soft(unsafe { soft::Soft::init() }))
+        cfg_if::cfg_if! {
+            if #[cfg(cubehash_portable)] {
+                Self::Portable(unsafe { portable::Portable::init(iv) })
+            } else {
+                Self::Scalar(unsafe { scalar::Scalar::init(iv) })
+            }
+        }
+    }
+
+    // build.rs compiled in only Soft and the backend the target features
+    // guarantee
+    #[cfg(cubehash_static)]
+    fn select(iv: &[u32; 32]) -> Self {
+        cfg_if::cfg_if! {
+            if #[cfg(cubehash_avx512)] {
+                Self::Avx512(unsafe { avx512::Avx512::init(iv) })
+            } else if #[cfg(cubehash_avx2)] {
+                Self::Avx2(unsafe { avx2::Avx2::init(iv) })
+            } else if #[cfg(cubehash_neon)] {
+                Self::Neon(unsafe { neon::Neon::init(iv) })
+            } else if #[cfg(cubehash_portable)] {
+                Self::Portable(unsafe { portable::Portable::init(iv) })
+            } else {
+                Self::Scalar(unsafe { scalar::Scalar::init(iv) })
+            }
+        }
+    }
+
+    fn check(backend: BackendSelector) -> Result<(), BackendError> {
+        let supported = match backend {
+            BackendSelector::Soft => true,
+            #[cfg(cubehash_scalar)]
+            BackendSelector::Scalar => true,
+            #[cfg(cubehash_sse2)]
+            BackendSelector::Sse2 => cpu_sse2::get(),
+            #[cfg(cubehash_avx2)]
+            BackendSelector::Avx2 => cpu_avx2::get(),
+            #[cfg(cubehash_avx512)]
+            BackendSelector::Avx512 => cpu_avx512::get(),
+            #[cfg(cubehash_avx512vl)]
+            BackendSelector::Avx512Vl => cpu_avx512vl::get(),
+            #[cfg(cubehash_neon)]
+            BackendSelector::Neon => cpu_neon::get(),
+            #[cfg(cubehash_portable)]
+            BackendSelector::Portable => true,
+            _ => return Err(BackendError::NotCompiledIn)
+        };
+
+        if supported { Ok(()) } else { Err(BackendError::UnsupportedCpu) }
+    }
+
+    pub(crate) fn new_with_backend(backend: BackendSelector, iv: &[u32; 32]) -> Result<Self, BackendError> {
+        Self::check(backend)?;
+
+        Ok(match backend {
+            BackendSelector::Soft => Self::Soft(unsafe { soft::Soft::init(iv) }),
+            #[cfg(cubehash_scalar)]
+            BackendSelector::Scalar => Self::Scalar(unsafe { scalar::Scalar::init(iv) }),
+            #[cfg(cubehash_sse2)]
+            BackendSelector::Sse2 => Self::Sse2(unsafe { sse2::Sse2::init(iv) }),
+            #[cfg(cubehash_avx2)]
+            BackendSelector::Avx2 => Self::Avx2(unsafe { avx2::Avx2::init(iv) }),
+            #[cfg(cubehash_avx512)]
+            BackendSelector::Avx512 => Self::Avx512(unsafe { avx512::Avx512::init(iv) }),
+            #[cfg(cubehash_avx512vl)]
+            BackendSelector::Avx512Vl => Self::Avx512Vl(unsafe { avx512vl::Avx512Vl::init(iv) }),
+            #[cfg(cubehash_neon)]
+            BackendSelector::Neon => Self::Neon(unsafe { neon::Neon::init(iv) }),
+            #[cfg(cubehash_portable)]
+            BackendSelector::Portable => Self::Portable(unsafe { portable::Portable::init(iv) }),
+            // check already rejected these
+            _ => unreachable!()
+        })
+    }
+
+    pub(crate) fn selector(&self) -> BackendSelector {
+        match self {
+            Self::Soft(_) => BackendSelector::Soft,
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(_) => BackendSelector::Scalar,
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(_) => BackendSelector::Sse2,
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(_) => BackendSelector::Avx2,
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(_) => BackendSelector::Avx512,
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(_) => BackendSelector::Avx512Vl,
+            #[cfg(cubehash_neon)]
+            Self::Neon(_) => BackendSelector::Neon,
+            #[cfg(cubehash_portable)]
+            Self::Portable(_) => BackendSelector::Portable
+        }
+    }
+
+    pub(crate) fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => unsafe { b.update_blocks(blocks, r) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => unsafe { b.update_blocks(blocks, r) },
+            Self::Soft(b) => unsafe { b.update_blocks(blocks, r) }
+        }
+    }
+
+    pub(crate) fn to_state(&self) -> [u32; 32] {
+        let mut buf = [0; 128];
+        self.output(&mut buf);
+        core::array::from_fn(|i| u32::from_le_bytes([buf[4*i], buf[4*i+1], buf[4*i+2], buf[4*i+3]]))
+    }
+
+    // b is in bytes; every message starts from the current state
+    pub(crate) fn hash_many<O: AsMut<[u8]>>(&self, b: usize, r: u16, f: u16, inputs: &[&[u8]], outputs: &mut [O]) {
+        let start = self.to_state();
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(_) => multi::hash_many::<4, O>(&start, b, r, f, inputs, outputs, |x, n| unsafe { sse2::permute_lanes(x, n) }),
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(_) => multi::hash_many::<8, O>(&start, b, r, f, inputs, outputs, |x, n| unsafe { avx2::permute_lanes(x, n) }),
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(_) => multi::hash_many::<16, O>(&start, b, r, f, inputs, outputs, |x, n| unsafe { avx512::permute_lanes(x, n) }),
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(_) => multi::hash_many::<8, O>(&start, b, r, f, inputs, outputs, |x, n| unsafe { avx512vl::permute_lanes(x, n) }),
+            #[cfg(cubehash_neon)]
+            Self::Neon(_) => multi::hash_many::<4, O>(&start, b, r, f, inputs, outputs, |x, n| unsafe { neon::permute_lanes(x, n) }),
+            #[cfg(cubehash_portable)]
+            Self::Portable(_) => multi::hash_many::<4, O>(&start, b, r, f, inputs, outputs, portable::permute_lanes),
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(_) => multi::hash_many::<1, O>(&start, b, r, f, inputs, outputs, scalar::permute_lanes),
+            Self::Soft(_) => multi::hash_many::<1, O>(&start, b, r, f, inputs, outputs, soft::permute_lanes)
+        }
+    }
+
+    pub(crate) fn finalize(&mut self, f: u16) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => unsafe { b.finalize(f) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => unsafe { b.finalize(f) },
+            Self::Soft(b) => unsafe { b.finalize(f) }
+        }
+    }
+
+    pub(crate) fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => unsafe { b.squeeze(out, r) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => unsafe { b.squeeze(out, r) },
+            Self::Soft(b) => unsafe { b.squeeze(out, r) }
+        }
+    }
+
+    pub(crate) fn output(&self, out: &mut [u8]) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => unsafe { b.output(out) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => unsafe { b.output(out) },
+            Self::Soft(b) => unsafe { b.output(out) }
+        }
+    }
+
+    pub(crate) fn unpermute(&mut self, rounds: u16) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => unsafe { b.unpermute(rounds) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => unsafe { b.unpermute(rounds) },
+            Self::Soft(b) => unsafe { b.unpermute(rounds) }
+        }
+    }
+
+    // Replaces the state while keeping the same backend
+    pub(crate) fn load(&mut self, state: &[u32; 32]) {
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => *b = unsafe { sse2::Sse2::init(state) },
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => *b = unsafe { avx2::Avx2::init(state) },
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => *b = unsafe { avx512::Avx512::init(state) },
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => *b = unsafe { avx512vl::Avx512Vl::init(state) },
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => *b = unsafe { neon::Neon::init(state) },
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => *b = unsafe { portable::Portable::init(state) },
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => *b = unsafe { scalar::Scalar::init(state) },
+            Self::Soft(b) => *b = unsafe { soft::Soft::init(state) }
+        }
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> UpdateCore for CubeHashCore<I, R, F, H> {
+// The second field is the IV the core started from, which is for fewer than H
+// bytes in a CubeHashVar core, so reset only has to reload it
+#[derive(Clone)]
+pub struct CubeHashCore<const I: u16, const R: u16, B, const F: u16, H>(Backend, [u32; 32], PhantomData<(B, H)>);
+
+impl<const I: u16, const R: u16, B, const F: u16, H> HashMarker for CubeHashCore<I, R, B, F, H> {}
+
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> AlgorithmName for CubeHashCore<I, R, B, F, H> {
+    fn write_alg_name(f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CubeHash{}+{}/{}+{}-{}", I, R, B::USIZE, F, H::USIZE * 8)
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> BlockSizeUser for CubeHashCore<I, R, B, F, H> {
+    type BlockSize = B;
+}
+
+impl<const I: u16, const R: u16, B, const F: u16, H: ArraySize> OutputSizeUser for CubeHashCore<I, R, B, F, H> {
+    type OutputSize = H;
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> BufferKindUser for CubeHashCore<I, R, B, F, H> {
+    type BufferKind = Eager;
+}
+
+#[cfg(feature = "selectable-backend")]
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> CubeHashCore<I, R, B, F, H> {
+    pub fn new_with_backend(backend: BackendSelector) -> Result<Self, BackendError> {
+        Backend::new_with_backend(backend, &Self::IV).map(|b| Self(b, Self::IV, PhantomData))
+    }
+}
+
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> CubeHashCore<I, R, B, F, H> {
+    const IV: [u32; 32] = iv(H::U16, B::U16, R, I);
+
+    pub fn backend(&self) -> BackendSelector {
+        self.0.selector()
+    }
+}
+
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> Default for CubeHashCore<I, R, B, F, H> {
+    fn default() -> Self {
+        Self(Backend::new(&Self::IV), Self::IV, PhantomData)
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> UpdateCore for CubeHashCore<I, R, B, F, H> {
     fn update_blocks(&mut self, blocks: &[Block<Self>]) {
-        match self.0 {
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Sse2(ref mut b) => unsafe {
-                for block in blocks.iter() { b.update_block(block) } }
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Avx2(ref mut b) => unsafe {
-                for block in blocks.iter() { b.update_block(block) } }
-            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-            Backend::Avx512(ref mut b) => unsafe {
-                for block in blocks.iter() { b.update_block(block) } },
-            #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-            Backend::Neon(ref mut b) => unsafe {
-                for block in blocks.iter() { b.update_block(block) } },
-            Backend::Soft(ref mut b) => unsafe {
-                for block in blocks.iter() { b.update_block(block) } }
-        }
-    }
-}
-
-impl<const I: u16, const R: u16, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> FixedOutputCore for CubeHashCore<I, R, F, H> {
+        self.0.update_blocks(blocks.iter().map(|block| &block[..]), R);
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> CubeHashCore<I, R, B, F, H> {
+    fn finalize(&mut self, buffer: &mut Buffer<Self>) {
+        let Self(backend, ..) = self;
+        buffer.digest_pad(0x80, &[], |block| backend.update_blocks([&block[..]], R));
+        backend.finalize(F);
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> FixedOutputCore for CubeHashCore<I, R, B, F, H> {
     fn finalize_fixed_core(&mut self, buffer: &mut Buffer<Self>, out: &mut Output<Self>) {
-        buffer.digest_pad(0x80, &[], |block| match self.0 {
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Sse2(ref mut b) => unsafe { b.update_block(block) },
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Avx2(ref mut b) => unsafe { b.update_block(block) },
-            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-            Backend::Avx512(ref mut b) => unsafe { b.update_block(block) },
-            #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-            Backend::Neon(ref mut b) => unsafe { b.update_block(block) },
-            Backend::Soft(ref mut b) => unsafe { b.update_block(block) }
-        });
-        match self.0 {
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Sse2(ref mut b) => unsafe { b.finalize(out) },
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Avx2(ref mut b) => unsafe { b.finalize(out) },
-            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-            Backend::Avx512(ref mut b) => unsafe { b.finalize(out) },
-            #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-            Backend::Neon(ref mut b) => unsafe { b.finalize(out) },
-            Backend::Soft(ref mut b) => unsafe { b.finalize(out) }
+        self.finalize(buffer);
+        self.0.output(out);
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> CubeHashCore<I, R, B, F, H> {
+    // Hashes each input into the matching output as if by a clone of this
+    // core, interleaving independent messages across SIMD lanes
+    pub fn hash_many(&self, inputs: &[&[u8]], outputs: &mut [Output<Self>]) {
+        self.0.hash_many(B::USIZE, R, F, inputs, outputs);
+    }
+}
+
+// H is the maximum output size here; the IV is derived from the requested one
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> VariableOutputCore for CubeHashCore<I, R, B, F, H> {
+    const TRUNC_SIDE: TruncSide = TruncSide::Left;
+
+    fn new(output_size: usize) -> Result<Self, InvalidOutputSize> {
+        if output_size == 0 || output_size > H::USIZE {
+            return Err(InvalidOutputSize);
         }
+        if output_size == H::USIZE {
+            return Ok(Self::default());
+        }
+        // the init rounds go through the backend rather than the const fn
+        let mut backend = Backend::new(&iv(output_size as u16, B::U16, R, 0));
+        backend.update_blocks([&[][..]], I);
+        let iv = backend.to_state();
+        Ok(Self(backend, iv, PhantomData))
+    }
+
+    fn finalize_variable_core(&mut self, buffer: &mut Buffer<Self>, out: &mut Output<Self>) {
+        self.finalize(buffer);
+        self.0.output(out);
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: BlockSizes + IsLessOrEqual<U64, Output = True>> ExtendableOutputCore for CubeHashCore<I, R, B, F, H> {
+    type ReaderCore = CubeHashReaderCore<I, R, B, F, H>;
+
+    fn finalize_xof_core(&mut self, buffer: &mut Buffer<Self>) -> Self::ReaderCore {
+        self.finalize(buffer);
+        CubeHashReaderCore(self.0.clone(), PhantomData)
+    }
+}
+
+// Squeezes h bytes per r rounds, so the first h bytes of output are the
+// fixed-size CubeHash digest
+#[derive(Clone)]
+pub struct CubeHashReaderCore<const I: u16, const R: u16, B, const F: u16, H>(Backend, PhantomData<(B, H)>);
+
+impl<const I: u16, const R: u16, B, const F: u16, H: BlockSizes> BlockSizeUser for CubeHashReaderCore<I, R, B, F, H> {
+    type BlockSize = H;
+}
+
+impl<const I: u16, const R: u16, B, const F: u16, H: BlockSizes + IsLessOrEqual<U64, Output = True>> XofReaderCore for CubeHashReaderCore<I, R, B, F, H> {
+    fn read_block(&mut self) -> Block<Self> {
+        let mut block = Block::<Self>::default();
+        self.0.squeeze(&mut block, R);
+        block
+    }
+}
+
+/// The bare CubeHash permutation on its 32-word state, for building other
+/// constructions on top of it.
+///
+/// One round is exactly `soft::Soft::round` from the reference code, word
+/// indices counting from 0:
+///
+/// 1. add `x[i]` into `x[i + 16]` for i < 16,
+/// 2. rotate `x[i]` left by 7 for i < 16,
+/// 3. swap `x[i]` with `x[i ^ 8]` for i < 16,
+/// 4. xor `x[i + 16]` into `x[i]` for i < 16,
+/// 5. swap `x[i + 16]` with `x[(i ^ 2) + 16]` for i < 16,
+/// 6. add `x[i]` into `x[i + 16]` for i < 16,
+/// 7. rotate `x[i]` left by 11 for i < 16,
+/// 8. swap `x[i]` with `x[i ^ 4]` for i < 16,
+/// 9. xor `x[i + 16]` into `x[i]` for i < 16,
+/// 10. swap `x[i + 16]` with `x[(i ^ 1) + 16]` for i < 16.
+///
+/// Bytes map onto the state little-endian, byte `n` being part of word
+/// `n / 4`. The backend is picked the same way as for [`CubeHashCore::default`].
+#[derive(Clone)]
+pub struct CubeHashPermutation(Backend);
+
+impl CubeHashPermutation {
+    pub fn new(state: [u32; 32]) -> Self {
+        Self(Backend::new(&state))
+    }
+
+    #[cfg(feature = "selectable-backend")]
+    pub fn new_with_backend(state: [u32; 32], backend: BackendSelector) -> Result<Self, BackendError> {
+        Backend::new_with_backend(backend, &state).map(Self)
+    }
+
+    pub fn backend(&self) -> BackendSelector {
+        self.0.selector()
+    }
+
+    pub fn state(&self) -> [u32; 32] {
+        self.0.to_state()
+    }
+
+    /// XORs `data` into the state starting at byte 0.
+    ///
+    /// # Panics
+    /// If `data` is longer than the 128-byte state.
+    pub fn xor_in(&mut self, data: &[u8]) {
+        assert!(data.len() <= 128, "data is longer than the CubeHash state");
+        self.0.update_blocks([data], 0);
+    }
+
+    /// Copies the state out starting at byte 0 until `out` is full.
+    ///
+    /// # Panics
+    /// If `out` is longer than the 128-byte state.
+    pub fn extract(&self, out: &mut [u8]) {
+        assert!(out.len() <= 128, "output is longer than the CubeHash state");
+        self.0.output(out);
+    }
+
+    pub fn permute(&mut self, rounds: u16) {
+        self.0.update_blocks([&[][..]], rounds);
+    }
+
+    /// Undoes `permute(rounds)`: each inverse round runs the steps above last
+    /// to first, subtracting instead of adding and rotating right.
+    pub fn unpermute(&mut self, rounds: u16) {
+        self.0.unpermute(rounds);
+    }
+}
+
+impl Default for CubeHashPermutation {
+    fn default() -> Self {
+        Self::new([0; 32])
+    }
+}
+
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> Reset for CubeHashCore<I, R, B, F, H> {
+    fn reset(&mut self) {
+        self.0.load(&self.1);
+    }
+}
+
+// The 32 state words in order, little-endian, whichever backend produced them,
+// then the IV the same way. The IV has to be one these parameters make for 1 to
+// H bytes of output, which is checked by undoing the init rounds. A restored
+// state runs on the default backend for this machine.
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> SerializableState for CubeHashCore<I, R, B, F, H> {
+    type SerializedStateSize = U256;
+
+    fn serialize(&self) -> SerializedState<Self> {
+        let mut out = SerializedState::<Self>::default();
+        for (chunk, word) in core::iter::zip(out.chunks_exact_mut(4), self.0.to_state().into_iter().chain(self.1)) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    fn deserialize(serialized_state: &SerializedState<Self>) -> Result<Self, DeserializeStateError> {
+        let words: [u32; 64] = core::array::from_fn(|i| u32::from_le_bytes(serialized_state[4*i..4*i+4].try_into().unwrap()));
+        let (state, init) = (words[..32].try_into().unwrap(), words[32..].try_into().unwrap());
+        let mut start = Backend::Soft(unsafe { soft::Soft::init(&init) });
+        start.unpermute(I);
+        let start = start.to_state();
+        if start[0] == 0 || start[0] > H::U32 || start[1..] != iv(0, B::U16, R, 0)[1..] {
+            return Err(DeserializeStateError);
+        }
+        Ok(Self(Backend::new(&state), init, PhantomData))
     }
 }
 
@@ -213,48 +805,95 @@ impl<const I: u16, const R: u16, const F: u16, H: ArraySize + IsGreater<U0, Outp
 use digest::zeroize::{Zeroize, ZeroizeOnDrop};
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> Zeroize for CubeHashCore<I, R, F, H> {
+impl Zeroize for Backend {
     fn zeroize(&mut self) {
-        match self.0 {
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Sse2(ref mut b) => b.zeroize(),
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            Backend::Avx2(ref mut b) => b.zeroize(),
-            #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
-            Backend::Avx512(ref mut b) => b.zeroize(),
-            #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
-            Backend::Neon(ref mut b) => b.zeroize(),
-            Backend::Soft(ref mut b) => b.zeroize()
+        match self {
+            #[cfg(cubehash_sse2)]
+            Self::Sse2(b) => b.zeroize(),
+            #[cfg(cubehash_avx2)]
+            Self::Avx2(b) => b.zeroize(),
+            #[cfg(cubehash_avx512)]
+            Self::Avx512(b) => b.zeroize(),
+            #[cfg(cubehash_avx512vl)]
+            Self::Avx512Vl(b) => b.zeroize(),
+            #[cfg(cubehash_neon)]
+            Self::Neon(b) => b.zeroize(),
+            #[cfg(cubehash_portable)]
+            Self::Portable(b) => b.zeroize(),
+            #[cfg(cubehash_scalar)]
+            Self::Scalar(b) => b.zeroize(),
+            Self::Soft(b) => b.zeroize()
         }
     }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> Drop for CubeHashCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B, const F: u16, H> Zeroize for CubeHashCore<I, R, B, F, H> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const I: u16, const R: u16, B, const F: u16, H> Drop for CubeHashCore<I, R, B, F, H> {
     fn drop(&mut self) {
         self.zeroize();
     }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> ZeroizeOnDrop for CubeHashCore<I, R, F, H> {}
+impl<const I: u16, const R: u16, B, const F: u16, H> ZeroizeOnDrop for CubeHashCore<I, R, B, F, H> {}
+
+#[cfg(feature = "zeroize")]
+impl Zeroize for CubeHashPermutation {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for CubeHashPermutation {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for CubeHashPermutation {}
+
+#[cfg(feature = "zeroize")]
+impl<const I: u16, const R: u16, B, const F: u16, H> Zeroize for CubeHashReaderCore<I, R, B, F, H> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const I: u16, const R: u16, B, const F: u16, H> Drop for CubeHashReaderCore<I, R, B, F, H> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const I: u16, const R: u16, B, const F: u16, H> ZeroizeOnDrop for CubeHashReaderCore<I, R, B, F, H> {}
 
 #[cfg(test)]
 mod test {
     extern crate alloc;
 
-    use digest::{Digest as _, core_api::CoreWrapper, consts::U56};
+    use digest::{Digest as _, core_api::CoreWrapper, consts::{U1, U3, U16, U17, U20, U28, U32, U48, U56, U64, U100}};
 
     use super::*;
 
-    type CubeHash448 = CoreWrapper<CubeHashCore<16, 16, 32, U56>>;
+    type CubeHash448 = CoreWrapper<CubeHashCore<16, 16, U32, 32, U56>>;
 
     #[inline]
     fn control() -> CubeHash448 {
-        CubeHash448::from_core(CubeHashCore(Backend::Soft(unsafe { soft::Soft::init() })))
+        CubeHash448::from_core(CubeHashCore(Backend::Soft(unsafe { soft::Soft::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData))
     }
 
-    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"))]
+    #[cfg(cubehash_avx512)]
     #[test]
     fn avx512_consistent() {
         if !cpu_avx512::get() {
@@ -270,7 +909,31 @@ mod test {
         };
 
         let thash = {
-            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Avx512(unsafe { avx512::Avx512::init() })));
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Avx512(unsafe { avx512::Avx512::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
+            uut.update(&data);
+            uut.finalize()
+        };
+
+        assert_eq!(chash, thash);
+    }
+
+    #[cfg(cubehash_avx512vl)]
+    #[test]
+    fn avx512vl_consistent() {
+        if !cpu_avx512vl::get() {
+            panic!("test cannot run on this system");
+        }
+
+        let data = alloc::vec![69; 1048574];
+
+        let chash = {
+            let mut control = control();
+            control.update(&data);
+            control.finalize()
+        };
+
+        let thash = {
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Avx512Vl(unsafe { avx512vl::Avx512Vl::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
             uut.update(&data);
             uut.finalize()
         };
@@ -278,7 +941,27 @@ mod test {
         assert_eq!(chash, thash);
     }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[cfg(cubehash_scalar)]
+    #[test]
+    fn scalar_consistent() {
+        let data = alloc::vec![69; 1048574];
+
+        let chash = {
+            let mut control = control();
+            control.update(&data);
+            control.finalize()
+        };
+
+        let thash = {
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Scalar(unsafe { scalar::Scalar::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
+            uut.update(&data);
+            uut.finalize()
+        };
+
+        assert_eq!(chash, thash);
+    }
+
+    #[cfg(cubehash_sse2)]
     #[test]
     fn sse2_consistent() {
         if !cpu_sse2::get() {
@@ -294,7 +977,7 @@ mod test {
         };
 
         let thash = {
-            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Sse2(unsafe { sse2::Sse2::init() })));
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Sse2(unsafe { sse2::Sse2::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
             uut.update(&data);
             uut.finalize()
         };
@@ -302,7 +985,7 @@ mod test {
         assert_eq!(chash, thash);
     }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[cfg(cubehash_avx2)]
     #[test]
     fn avx2_consistent() {
         if !cpu_avx2::get() {
@@ -318,7 +1001,7 @@ mod test {
         };
 
         let thash = {
-            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Avx2(unsafe { avx2::Avx2::init() })));
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Avx2(unsafe { avx2::Avx2::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
             uut.update(&data);
             uut.finalize()
         };
@@ -326,7 +1009,7 @@ mod test {
         assert_eq!(chash, thash);
     }
 
-    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+    #[cfg(cubehash_neon)]
     #[test]
     fn neon_consistent() {
         if !cpu_neon::get() {
@@ -342,11 +1025,417 @@ mod test {
         };
 
         let thash = {
-            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Neon(unsafe { neon::Neon::init() })));
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Neon(unsafe { neon::Neon::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
             uut.update(&data);
             uut.finalize()
         };
 
         assert_eq!(chash, thash);
     }
-}
\ No newline at end of file
+    #[cfg(cubehash_portable)]
+    #[test]
+    fn portable_consistent() {
+        let data = alloc::vec![69; 1048574];
+
+        let chash = {
+            let mut control = control();
+            control.update(&data);
+            control.finalize()
+        };
+
+        let thash = {
+            let mut uut = CubeHash448::from_core(CubeHashCore(Backend::Portable(unsafe { portable::Portable::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
+            uut.update(&data);
+            uut.finalize()
+        };
+
+        assert_eq!(chash, thash);
+    }
+
+    fn uut_backends<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned>() -> alloc::vec::Vec<CubeHashCore<I, R, B, F, H>> {
+        let mut uuts = alloc::vec::Vec::new();
+        #[cfg(cubehash_scalar)]
+        uuts.push(CubeHashCore(Backend::Scalar(unsafe { scalar::Scalar::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        #[cfg(cubehash_sse2)]
+        if cpu_sse2::get() {
+            uuts.push(CubeHashCore(Backend::Sse2(unsafe { sse2::Sse2::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        }
+        #[cfg(cubehash_avx2)]
+        if cpu_avx2::get() {
+            uuts.push(CubeHashCore(Backend::Avx2(unsafe { avx2::Avx2::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        }
+        #[cfg(cubehash_avx512)]
+        if cpu_avx512::get() {
+            uuts.push(CubeHashCore(Backend::Avx512(unsafe { avx512::Avx512::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        }
+        #[cfg(cubehash_avx512vl)]
+        if cpu_avx512vl::get() {
+            uuts.push(CubeHashCore(Backend::Avx512Vl(unsafe { avx512vl::Avx512Vl::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        }
+        #[cfg(cubehash_neon)]
+        if cpu_neon::get() {
+            uuts.push(CubeHashCore(Backend::Neon(unsafe { neon::Neon::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        }
+        #[cfg(cubehash_portable)]
+        uuts.push(CubeHashCore(Backend::Portable(unsafe { portable::Portable::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+        uuts
+    }
+
+    fn block_size_consistent<B: BlockSizes + IsLessOrEqual<U128, Output = True>>() {
+        let data = alloc::vec![69; 4099];
+
+        let chash = {
+            let mut control = CoreWrapper::from_core(CubeHashCore::<16, 16, B, 32, U56>(Backend::Soft(unsafe { soft::Soft::init(&iv(56, B::U16, 16, 16)) }), iv(56, B::U16, 16, 16), PhantomData));
+            control.update(&data);
+            control.finalize()
+        };
+
+        for uut in uut_backends::<16, 16, B, 32, U56>() {
+            let mut uut = CoreWrapper::from_core(uut);
+            uut.update(&data);
+            assert_eq!(chash, uut.finalize());
+        }
+    }
+
+    #[test]
+    fn block_sizes_consistent() {
+        block_size_consistent::<U1>();
+        block_size_consistent::<U3>();
+        block_size_consistent::<U17>();
+        block_size_consistent::<U32>();
+        block_size_consistent::<U48>();
+        block_size_consistent::<U100>();
+        block_size_consistent::<U128>();
+    }
+
+    // Follows digest::dev::fixed_reset_test step for step, on every backend,
+    // for each known answer with these parameters. Returns how many it ran.
+    fn kat<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>>() -> usize {
+        let kats = alloc::vec::Vec::from_iter(kat::KATS.into_iter().chain(kat::COMPUTED).filter(|&(i, r, b, f, _, expected)| (i, r, b, f, expected.len()) == (I, R, B::U16, F, H::USIZE)));
+
+        let mut cores = uut_backends::<I, R, B, F, H>();
+        cores.push(CubeHashCore(Backend::Soft(unsafe { soft::Soft::init(&iv(H::U16, B::U16, R, I)) }), iv(H::U16, B::U16, R, I), PhantomData));
+
+        for (.., msg, expected) in &kats {
+            for core in &cores {
+                let backend = core.backend();
+
+                let mut h = CoreWrapper::from_core(core.clone());
+                h.update(msg);
+                let mut h2 = h.clone();
+                assert_eq!(h.finalize()[..], **expected, "{backend}: whole message");
+
+                Reset::reset(&mut h2);
+                h2.update(msg);
+                assert_eq!(h2.finalize_reset()[..], **expected, "{backend}: whole message after reset");
+
+                for n in 1..17.min(msg.len()) {
+                    let mut h = CoreWrapper::from_core(core.clone());
+                    for chunk in msg.chunks(n) {
+                        h.update(chunk);
+                        h2.update(chunk);
+                    }
+                    assert_eq!(h.finalize()[..], **expected, "{backend}: message in chunks of {n}");
+                    assert_eq!(h2.finalize_reset()[..], **expected, "{backend}: message in chunks of {n} after reset");
+                }
+            }
+        }
+        kats.len()
+    }
+
+    #[test]
+    fn known_answers() {
+        let ran = kat::<160, 16, U32, 160, U32>()
+            + kat::<160, 16, U32, 160, U64>()
+            + kat::<80, 8, U1, 80, U64>()
+            + kat::<10, 1, U1, 10, U64>()
+            + kat::<16, 16, U32, 32, U16>()
+            + kat::<16, 16, U32, 32, U20>()
+            + kat::<16, 16, U32, 32, U28>()
+            + kat::<16, 16, U32, 32, U32>()
+            + kat::<16, 16, U32, 32, U48>()
+            + kat::<16, 16, U32, 32, U64>();
+        assert_eq!(ran, kat::KATS.len() + kat::COMPUTED.len());
+    }
+
+    fn hash_many_consistent<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16>() {
+        let d: alloc::vec::Vec<u8> = (0..2000).map(|x| (x * 7) as u8).collect();
+        let inputs: alloc::vec::Vec<&[u8]> = (0..53).map(|i| &d[i * 7..i * 7 + (i * i * 13) % 700]).collect();
+
+        let control = CubeHashCore::<I, R, B, F, U56>(Backend::Soft(unsafe { soft::Soft::init(&iv(56, B::U16, R, I)) }), iv(56, B::U16, R, I), PhantomData);
+
+        let mut uuts = uut_backends::<I, R, B, F, U56>();
+        uuts.push(control.clone());
+
+        for uut in uuts {
+            let mut outputs = alloc::vec![Output::<CubeHashCore<I, R, B, F, U56>>::default(); inputs.len()];
+            uut.hash_many(&inputs, &mut outputs);
+            for (input, output) in core::iter::zip(&inputs, &outputs) {
+                assert_eq!(*output, CoreWrapper::from_core(control.clone()).chain_update(input).finalize());
+            }
+        }
+    }
+
+    #[test]
+    fn hash_many_params_consistent() {
+        hash_many_consistent::<16, 16, U32, 32>();
+        hash_many_consistent::<16, 16, U17, 32>();
+        hash_many_consistent::<10, 1, U1, 10>();
+        hash_many_consistent::<3, 5, U100, 7>();
+    }
+
+    #[test]
+    fn backend_introspection() {
+        let available = alloc::vec::Vec::from_iter(available_backends());
+        assert!(available.contains(&CubeHashCore::<16, 16, U32, 32, U56>::default().backend()));
+
+        #[cfg(feature = "selectable-backend")]
+        for backend in BackendSelector::ALL {
+            match CubeHashCore::<16, 16, U32, 32, U56>::new_with_backend(backend) {
+                Ok(core) => {
+                    assert!(available.contains(&backend));
+                    assert_eq!(core.backend(), backend);
+                },
+                Err(_) => assert!(!available.contains(&backend))
+            }
+        }
+
+        #[cfg(cubehash_sse2)]
+        assert_eq!(Backend::check(BackendSelector::Neon), Err(BackendError::NotCompiledIn));
+        #[cfg(cubehash_neon)]
+        assert_eq!(Backend::check(BackendSelector::Sse2), Err(BackendError::NotCompiledIn));
+    }
+
+    // held by tests that depend on or change the process-wide default backend
+    #[cfg(feature = "std")]
+    static DEFAULT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn backend_override() {
+        let _lock = DEFAULT_LOCK.lock().unwrap();
+
+        set_default_backend(Some(BackendSelector::Soft)).unwrap();
+        assert_eq!(default_backend_override(), Some(BackendSelector::Soft));
+        assert_eq!(CubeHashCore::<16, 16, U32, 32, U56>::default().backend(), BackendSelector::Soft);
+
+        #[cfg(cubehash_sse2)]
+        assert_eq!(set_default_backend(Some(BackendSelector::Neon)), Err(BackendError::NotCompiledIn));
+        // a static build has its own backend and Soft, anything else would be
+        // silently ignored
+        #[cfg(cubehash_static)]
+        assert_eq!(available_backends().count(), 2);
+        assert_eq!(default_backend_override(), Some(BackendSelector::Soft));
+
+        if std::env::var_os("CUBEHASH_BACKEND").is_none() {
+            assert_eq!(rejected_env_backend(), None);
+        }
+
+        set_default_backend(None).unwrap();
+        assert_eq!(default_backend_override(), None);
+
+        assert_eq!("AVX512VL".parse(), Ok(BackendSelector::Avx512Vl));
+        assert_eq!("mmx".parse::<BackendSelector>(), Err(BackendError::UnknownName));
+    }
+
+    #[cfg(all(feature = "self-test", feature = "std"))]
+    #[test]
+    fn self_test_fallback() {
+        let _lock = DEFAULT_LOCK.lock().unwrap();
+
+        for backend in available_backends() {
+            assert_eq!(selftest::self_test(backend), Ok(true));
+        }
+
+        let chosen = Backend::select(&iv(56, 32, 16, 16)).selector();
+        if chosen != BackendSelector::Soft {
+            selftest::mark_failed(chosen, true);
+            assert_eq!(CubeHashCore::<16, 16, U32, 32, U56>::default().backend(), BackendSelector::Soft);
+            selftest::mark_failed(chosen, false);
+            assert_eq!(CubeHashCore::<16, 16, U32, 32, U56>::default().backend(), chosen);
+        }
+    }
+
+    #[cfg(feature = "autotune")]
+    #[test]
+    fn autotune_default() {
+        let _lock = DEFAULT_LOCK.lock().unwrap();
+        let tune = autotune::autotune();
+        assert!(tune.timings.iter().any(|&(backend, _)| backend == tune.backend));
+        #[cfg(not(cubehash_static))]
+        assert_eq!(CubeHashCore::<16, 16, U32, 32, U56>::default().backend(), tune.backend);
+    }
+
+    #[test]
+    fn reset_consistent() {
+        let expected = control().chain_update(b"abc").finalize();
+
+        let mut cores = uut_backends::<16, 16, U32, 32, U56>();
+        cores.push(CubeHashCore(Backend::Soft(unsafe { soft::Soft::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
+
+        for core in cores {
+            let backend = core.backend();
+            let mut h = CubeHash448::from_core(core);
+            h.update([69; 100]);
+            h.finalize_reset();
+            h.update(b"abc");
+            assert_eq!(h.finalize_reset(), expected, "{backend}");
+            h.update(b"abc");
+            assert_eq!(h.finalize(), expected, "{backend}");
+        }
+    }
+
+    #[test]
+    fn serialization_consistent() {
+        let data: alloc::vec::Vec<u8> = (0..1000).map(|x| (x * 3) as u8).collect();
+        let (head, tail) = data.split_at(517);
+        let expected = control().chain_update(&data).finalize();
+
+        let mut cores = uut_backends::<16, 16, U32, 32, U56>();
+        cores.push(CubeHashCore(Backend::Soft(unsafe { soft::Soft::init(&iv(56, 32, 16, 16)) }), iv(56, 32, 16, 16), PhantomData));
+
+        let mut saved = None;
+        for core in cores {
+            let state = CubeHash448::from_core(core).chain_update(head).serialize();
+            assert_eq!(*saved.get_or_insert(state), state, "encoding depends on the backend");
+
+            let h = CubeHash448::deserialize(&state).unwrap();
+            assert_eq!(h.chain_update(tail).finalize(), expected);
+        }
+
+        // resume a saved core and finish the message on every backend, not
+        // just the default one; the split is on a block boundary so the core
+        // holds everything before it
+        let (head, tail) = data.split_at(512);
+        let mut core = CubeHashCore::<16, 16, U32, 32, U56>::default();
+        let blocks = alloc::vec::Vec::from_iter(head.chunks(32).map(|block| Block::<CubeHashCore<16, 16, U32, 32, U56>>::try_from(block).unwrap()));
+        core.update_blocks(&blocks);
+        let restored = CubeHashCore::<16, 16, U32, 32, U56>::deserialize(&core.serialize()).unwrap();
+        for backend in available_backends() {
+            let resumed = CubeHashCore::<16, 16, U32, 32, U56>(Backend::new_with_backend(backend, &restored.0.to_state()).unwrap(), restored.1, PhantomData);
+            assert_eq!(resumed.backend(), backend);
+            assert_eq!(CubeHash448::from_core(resumed).chain_update(tail).finalize(), expected, "{backend}");
+        }
+    }
+
+    #[test]
+    fn deserialize_checks_iv() {
+        type Core<const I: u16, const R: u16, const F: u16, H> = CubeHashCore<I, R, U32, F, H>;
+
+        // the same IV, or one for a shorter output, is fine
+        let state = Core::<16, 16, 32, U32>::default().serialize();
+        assert!(Core::<16, 16, 32, U32>::deserialize(&state).is_ok());
+        assert!(Core::<16, 16, 32, U56>::deserialize(&state).is_ok());
+
+        // but not one for a longer output or other parameters
+        assert!(Core::<16, 16, 32, U28>::deserialize(&state).is_err());
+        assert!(Core::<160, 16, 160, U32>::deserialize(&state).is_err());
+        assert!(Core::<16, 16, 32, U32>::deserialize(&Core::<16, 8, 32, U32>::default().serialize()).is_err());
+
+        // nor a mangled one
+        let mut state = state;
+        state[200] ^= 1;
+        assert!(Core::<16, 16, 32, U32>::deserialize(&state).is_err());
+    }
+
+    #[test]
+    fn const_iv() {
+        // CubeHash160+16/32+160-512 IV, as hardcoded in sphlib
+        const IV: [u32; 32] = iv(64, 32, 16, 160);
+        assert_eq!(IV[..4], [0x2aea2a61, 0x50f494d4, 0x2d538b8b, 0x4167d83e]);
+        assert_eq!(IV, CubeHashCore::<160, 16, U32, 160, digest::consts::U64>::IV);
+    }
+
+    #[test]
+    fn const_digest_block_sizes() {
+        let d = [69; 1000];
+        assert_eq!(const_digest::<56>(16, 16, 17, 32, &d), CoreWrapper::<CubeHashCore<16, 16, U17, 32, U56>>::digest(d)[..]);
+        assert_eq!(const_digest::<56>(16, 16, 3, 32, &d), CoreWrapper::<CubeHashCore<16, 16, U3, 32, U56>>::digest(d)[..]);
+    }
+
+    #[test]
+    fn xof_consistent() {
+        use digest::{ExtendableOutput as _, XofReader as _};
+
+        let data = alloc::vec![69; 4099];
+
+        let mut cxof = [0; 1000];
+        let chash = {
+            let mut control = control();
+            control.update(&data);
+            control.clone().finalize_xof().read(&mut cxof);
+            control.finalize()
+        };
+        assert_eq!(chash[..], cxof[..chash.len()]);
+
+        for uut in uut_backends::<16, 16, U32, 32, U56>() {
+            let mut uut = CubeHash448::from_core(uut);
+            uut.update(&data);
+            let mut txof = [0; 1000];
+            uut.finalize_xof().read(&mut txof);
+            assert_eq!(cxof, txof);
+        }
+    }
+
+    #[test]
+    fn permutation_consistent() {
+        // the IV is (h, b, r, 0, ...) after i rounds
+        let mut perm = CubeHashPermutation::default();
+        perm.xor_in(&[64, 0, 0, 0, 32, 0, 0, 0, 16]);
+        perm.permute(160);
+        assert_eq!(perm.state(), iv(64, 32, 16, 160));
+
+        let start = core::array::from_fn(|i| (i as u32).wrapping_mul(0x9e3779b9));
+        let data: [u8; 77] = core::array::from_fn(|i| i as u8 ^ 0x5a);
+        let run = |mut perm: CubeHashPermutation| {
+            let mut out = [0; 93];
+            for rounds in [0, 1, 2, 3, 16, 17] {
+                perm.xor_in(&data[..rounds as usize * 4 + 5]);
+                perm.permute(rounds);
+            }
+            perm.extract(&mut out);
+            (perm.state(), out)
+        };
+
+        let control = run(CubeHashPermutation(Backend::Soft(unsafe { soft::Soft::init(&start) })));
+        for uut in uut_backends::<16, 16, U32, 32, U64>() {
+            let mut backend = uut.0.clone();
+            backend.load(&start);
+            assert_eq!(run(CubeHashPermutation(backend)), control);
+        }
+    }
+
+    #[test]
+    fn unpermute_consistent() {
+        // unwinding the IV rounds gets back to (h, b, r, 0, ...)
+        let mut perm = CubeHashPermutation::new(iv(64, 32, 16, 160));
+        perm.unpermute(160);
+        let mut start = [0; 32];
+        start[..3].copy_from_slice(&[64, 32, 16]);
+        assert_eq!(perm.state(), start);
+
+        let start = core::array::from_fn(|i| (i as u32).wrapping_mul(0x9e3779b9) ^ 0xdeadbeef);
+        let mut control = CubeHashPermutation(Backend::Soft(unsafe { soft::Soft::init(&start) }));
+        control.unpermute(17);
+
+        for uut in uut_backends::<16, 16, U32, 32, U64>() {
+            for rounds in [0, 1, 2, 3, 16, 17] {
+                let mut backend = uut.0.clone();
+                backend.load(&start);
+                let mut perm = CubeHashPermutation(backend);
+                perm.permute(rounds);
+                perm.unpermute(rounds);
+                assert_eq!(perm.state(), start, "{} {rounds}", perm.backend());
+                perm.unpermute(rounds);
+                perm.permute(rounds);
+                assert_eq!(perm.state(), start, "{} {rounds}", perm.backend());
+            }
+
+            let mut backend = uut.0.clone();
+            backend.load(&start);
+            let mut perm = CubeHashPermutation(backend);
+            perm.unpermute(17);
+            assert_eq!(perm.state(), control.state(), "{}", perm.backend());
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::{hint::black_box, sync::OnceLock, time::{Duration, Instant}, vec::Vec};
+
+use super::{available_backends, iv, Backend, BackendSelector};
+
+// What the default backend is and the timings it was picked from
+#[derive(Clone, Debug)]
+pub struct Autotune {
+    pub backend: BackendSelector,
+    pub timings: Vec<(BackendSelector, Duration)>
+}
+
+static AUTOTUNE: OnceLock<Autotune> = OnceLock::new();
+
+// Times every available backend on the first call and caches the fastest for
+// the rest of the process. Hashers created through Default use this choice.
+pub fn autotune() -> &'static Autotune {
+    AUTOTUNE.get_or_init(|| {
+        // CubeHash16+16/32+32-512 over 16 KiB, best of a few runs
+        let iv = iv(64, 32, 16, 16);
+        let data = [0; 16384];
+
+        let timings: Vec<_> = available_backends()
+            .filter(|&backend| backend != BackendSelector::Soft)
+            .map(|backend| {
+                let best = (0..4).map(|_| {
+                    let mut state = Backend::new_with_backend(backend, &iv).unwrap();
+                    let t0 = Instant::now();
+                    state.update_blocks(black_box(&data[..]).chunks(32), 16);
+                    black_box(state.to_state());
+                    t0.elapsed()
+                }).min().unwrap();
+                (backend, best)
+            })
+            .collect();
+
+        let backend = timings.iter().min_by_key(|(_, t)| *t).unwrap().0;
+        Autotune { backend, timings }
+    })
+}
@@ -3,24 +3,22 @@ use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 
-use core::{iter, marker::PhantomData, mem};
+use core::{iter, mem};
 
-use super::{CubeHashCore, CubeHashBackend};
-use digest::{array::{Array, ArraySize}, consts::U32, core_api::BlockSizeUser, typenum::{consts::{U0, U64}, IsGreater, IsLessOrEqual, True, Unsigned}};
+use super::CubeHashBackend;
 use static_assertions::const_assert_eq;
 
-const_assert_eq!(<CubeHashCore::<16, 16, 32, U64> as BlockSizeUser>::BlockSize::USIZE, mem::size_of::<__m256i>());
+const_assert_eq!(mem::size_of::<Avx2>(), 128);
 
 #[derive(Clone)]
-pub struct Avx2<const I: u16, const R: u16, const F: u16, H> {
+pub struct Avx2 {
     r00: __m256i,
     r01: __m256i,
     r10: __m256i,
-    r11: __m256i,
-    _phantom: PhantomData<H>
+    r11: __m256i
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> Avx2<I, R, F, H> {
+impl Avx2 {
     #[inline]
     #[target_feature(enable = "avx2")]
     unsafe fn round(&mut self) {
@@ -82,55 +80,153 @@ impl<const I: u16, const R: u16, const F: u16, H> Avx2<I, R, F, H> {
         self.r10 = r10;
         self.r11 = r11;
     }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn inv_round(&mut self) {
+        let Self { r00, r01, r10, r11, .. } = *self;
+
+        // 10. swap
+        let r10 = _mm256_shuffle_epi32(r10, 0xB1);
+        let r11 = _mm256_shuffle_epi32(r11, 0xB1);
+
+        // 9. xor
+        let r00 = _mm256_xor_si256(r00, r10);
+        let r01 = _mm256_xor_si256(r01, r11);
+
+        // 8. swap
+        let r00 = _mm256_permute4x64_epi64(r00, 0x4E);
+        let r01 = _mm256_permute4x64_epi64(r01, 0x4E);
+
+        // 7. rotate
+        let r00 = _mm256_or_si256(
+            _mm256_srli_epi32(r00, 11),
+            _mm256_slli_epi32(r00, 21)
+        );
+        let r01 = _mm256_or_si256(
+            _mm256_srli_epi32(r01, 11),
+            _mm256_slli_epi32(r01, 21)
+        );
+
+        // 6. subtract
+        let r10 = _mm256_sub_epi32(r10, r00);
+        let r11 = _mm256_sub_epi32(r11, r01);
+
+        // 5. swap
+        let r10 = _mm256_shuffle_epi32(r10, 0x4E);
+        let r11 = _mm256_shuffle_epi32(r11, 0x4E);
+
+        // 4. xor
+        let r00 = _mm256_xor_si256(r00, r10);
+        let r01 = _mm256_xor_si256(r01, r11);
+
+        // 3. swap
+        let (r00, r01) = (r01, r00);
+
+        // 2. rotate
+        let r00 = _mm256_or_si256(
+            _mm256_srli_epi32(r00, 7),
+            _mm256_slli_epi32(r00, 25)
+        );
+        let r01 = _mm256_or_si256(
+            _mm256_srli_epi32(r01, 7),
+            _mm256_slli_epi32(r01, 25)
+        );
+
+        // 1. subtract
+        let r10 = _mm256_sub_epi32(r10, r00);
+        let r11 = _mm256_sub_epi32(r11, r01);
+
+        self.r00 = r00;
+        self.r01 = r01;
+        self.r10 = r10;
+        self.r11 = r11;
+    }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> CubeHashBackend<I, R, F, H> for Avx2<I, R, F, H> {
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => { _mm256_or_si256(_mm256_slli_epi32($v, $l), _mm256_srli_epi32($v, $r)) };
+}
+
+#[target_feature(enable = "avx,avx2")]
+pub(super) unsafe fn permute_lanes(x: &mut [[u32; 8]; 32], rounds: u16) {
+    let mut v: [__m256i; 32] = mem::transmute(*x);
+    for _ in 0..rounds {
+        lane_round!(v, _mm256_add_epi32, _mm256_xor_si256, rotl);
+    }
+    *x = mem::transmute::<[__m256i; 32], [[u32; 8]; 32]>(v);
+}
+
+impl CubeHashBackend for Avx2 {
     #[inline]
     #[target_feature(enable = "avx,avx2")]
-    unsafe fn init() -> Self where H: Unsigned {
-        let mut init = Self {
-            r00: _mm256_setr_epi32(
-                H::I32,
-                <CubeHashCore<I, R, F, H> as BlockSizeUser>::BlockSize::I32,
-                R.into(),
-                0, 0, 0, 0, 0
-            ),
-            r01: _mm256_setzero_si256(),
-            r10: _mm256_setzero_si256(),
-            r11: _mm256_setzero_si256(),
-            _phantom: PhantomData
-        };
-        // the compiler precomputes this?? incredible
-        for _ in 0..I {
-            init.round();
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        let iv = iv.as_ptr() as *const __m256i;
+        Self {
+            r00: _mm256_loadu_si256(iv),
+            r01: _mm256_loadu_si256(iv.add(1)),
+            r10: _mm256_loadu_si256(iv.add(2)),
+            r11: _mm256_loadu_si256(iv.add(3))
         }
-        init
     }
 
     #[inline]
     #[target_feature(enable = "avx,avx2")]
-    unsafe fn update_block(&mut self, block: &Array<u8, U32>) {
-        self.r00 = _mm256_xor_si256(self.r00, _mm256_loadu_si256(block.as_ptr() as *const __m256i));
-        for _ in 0..R {
-            self.round();
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        // work on a local copy so the state stays in registers across blocks
+        let mut state = self.clone();
+
+        for block in blocks {
+            let Self { r00, r01, r10, r11, .. } = &mut state;
+
+            for (reg, chunk) in iter::zip([r00, r01, r10, r11], block.chunks(32)) {
+                let x = if chunk.len() == 32 {
+                    _mm256_loadu_si256(chunk.as_ptr() as *const __m256i)
+                } else {
+                    let mut buf = [0; 32];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    _mm256_loadu_si256(buf.as_ptr() as *const __m256i)
+                };
+                *reg = _mm256_xor_si256(*reg, x);
+            }
+
+            for _ in 0..r {
+                state.round();
+            }
         }
+
+        *self = state;
     }
 
     #[inline]
     #[target_feature(enable = "avx,avx2")]
-    unsafe fn finalize(&mut self, out: &mut Array<u8, H>) where H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True> {
+    unsafe fn finalize(&mut self, f: u16) {
         self.r11 = _mm256_xor_si256(
             self.r11,
             _mm256_setr_epi32(0, 0, 0, 0, 0, 0, 0, 1)
         );
 
-        for _ in 0..F {
+        for _ in 0..f {
+            self.round();
+        }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx,avx2")]
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
+
+        for _ in 0..r {
             self.round();
         }
+    }
 
-        let &mut Self { r00, r01, .. } = self;
-        
-        for (chunk, r) in iter::zip(out.chunks_mut(32), [r00, r01]) {
+    #[inline]
+    #[target_feature(enable = "avx,avx2")]
+    unsafe fn output(&self, out: &mut [u8]) {
+        let &Self { r00, r01, r10, r11 } = self;
+
+        for (chunk, r) in iter::zip(out.chunks_mut(32), [r00, r01, r10, r11]) {
             if chunk.len() == 32 {
                 _mm256_storeu_si256(chunk.as_mut_ptr() as *mut __m256i, r);
             } else {
@@ -141,10 +237,18 @@ impl<const I: u16, const R: u16, const F: u16, H> CubeHashBackend<I, R, F, H> fo
             }
         }
     }
+
+    #[inline]
+    #[target_feature(enable = "avx,avx2")]
+    unsafe fn unpermute(&mut self, rounds: u16) {
+        for _ in 0..rounds {
+            self.inv_round();
+        }
+    }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> digest::zeroize::Zeroize for Avx2<I, R, F, H> {
+impl digest::zeroize::Zeroize for Avx2 {
     fn zeroize(&mut self) {
         let Self { r00, r01, r10, r11, .. } = self;
         r00.zeroize();
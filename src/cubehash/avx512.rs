@@ -3,22 +3,20 @@ use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 
-use core::{marker::PhantomData, mem};
+use core::{iter, mem};
 
-use super::{CubeHashCore, CubeHashBackend};
-use digest::{array::{Array, ArraySize}, consts::U32, core_api::BlockSizeUser, typenum::{consts::{U0, U64}, IsGreater, IsLessOrEqual, True, Unsigned}};
+use super::CubeHashBackend;
 use static_assertions::const_assert_eq;
 
-const_assert_eq!(<CubeHashCore::<16, 16, 32, U64> as BlockSizeUser>::BlockSize::USIZE, mem::size_of::<__m256i>());
+const_assert_eq!(mem::size_of::<Avx512>(), 128);
 
 #[derive(Clone)]
-pub struct Avx512<const I: u16, const R: u16, const F: u16, H> {
+pub struct Avx512 {
     r0: __m512i,
-    r1: __m512i,
-    _phantom: PhantomData<H>
+    r1: __m512i
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> Avx512<I, R, F, H> {
+impl Avx512 {
     #[inline]
     #[target_feature(enable = "avx512f")]
     unsafe fn round(&mut self) {
@@ -38,60 +36,100 @@ impl<const I: u16, const R: u16, const F: u16, H> Avx512<I, R, F, H> {
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> CubeHashBackend<I, R, F, H> for Avx512<I, R, F, H> {
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => { _mm512_rol_epi32($v, $l) };
+}
+
+#[target_feature(enable = "avx512f")]
+pub(super) unsafe fn permute_lanes(x: &mut [[u32; 16]; 32], rounds: u16) {
+    let mut v: [__m512i; 32] = mem::transmute(*x);
+    for _ in 0..rounds {
+        lane_round!(v, _mm512_add_epi32, _mm512_xor_epi32, rotl);
+    }
+    *x = mem::transmute::<[__m512i; 32], [[u32; 16]; 32]>(v);
+}
+
+impl CubeHashBackend for Avx512 {
     #[inline]
     #[target_feature(enable = "avx512f")]
-    unsafe fn init() -> Self where H: Unsigned {
-        let mut init = Self {
-            r0: _mm512_setr_epi32(
-                H::I32,
-                <CubeHashCore<I, R, F, H> as BlockSizeUser>::BlockSize::I32,
-                R.into(),
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
-            ),
-            r1: _mm512_setzero_epi32(),
-            _phantom: PhantomData
-        };
-        // the compiler precomputes this?? incredible
-        for _ in 0..I {
-            init.round();
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        let iv = iv.as_ptr() as *const i32;
+        Self {
+            r0: _mm512_loadu_epi32(iv),
+            r1: _mm512_loadu_epi32(iv.add(16))
         }
-        init
     }
 
     #[inline]
     #[target_feature(enable = "avx,avx512f")]
-    unsafe fn update_block(&mut self, block: &Array<u8, U32>) {
-        let c = _mm512_zextsi256_si512(_mm256_loadu_si256(block.as_ptr() as *const __m256i));
-        self.r0 = _mm512_xor_epi32(self.r0, c);
-        for _ in 0..R {
-            self.round();
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        // work on a local copy so the state stays in registers across blocks
+        let mut state = self.clone();
+
+        for block in blocks {
+            let Self { r0, r1, .. } = &mut state;
+
+            for (reg, chunk) in iter::zip([r0, r1], block.chunks(64)) {
+                let c = match chunk.len() {
+                    64 => _mm512_loadu_si512(chunk.as_ptr() as *const _),
+                    32 => _mm512_zextsi256_si512(_mm256_loadu_si256(chunk.as_ptr() as *const __m256i)),
+                    l => {
+                        let mut buf = [0; 64];
+                        buf[..l].copy_from_slice(chunk);
+                        _mm512_loadu_si512(buf.as_ptr() as *const _)
+                    }
+                };
+                *reg = _mm512_xor_epi32(*reg, c);
+            }
+
+            for _ in 0..r {
+                state.round();
+            }
         }
+
+        *self = state;
     }
 
     #[inline]
     #[target_feature(enable = "avx512f")]
-    unsafe fn finalize(&mut self, out: &mut Array<u8, H>) where H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True> {
+    unsafe fn finalize(&mut self, f: u16) {
         self.r1 = _mm512_xor_epi32(
             self.r1,
             _mm512_setr_epi32(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1)
         );
-        for _ in 0..F {
+        for _ in 0..f {
             self.round();
         }
-        if H::USIZE == 64 {
-            _mm512_storeu_epi32(out.as_mut_ptr() as *mut i32, self.r0);
-        } else {
-            let mut buf = [0; 64];
-            _mm512_storeu_epi32(buf.as_mut_ptr() as *mut i32, self.r0);
-            let l = out.len();
-            out.copy_from_slice(&buf[..l]);
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f")]
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
+
+        for _ in 0..r {
+            self.round();
+        }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f")]
+    unsafe fn output(&self, out: &mut [u8]) {
+        for (chunk, r) in iter::zip(out.chunks_mut(64), [self.r0, self.r1]) {
+            if chunk.len() == 64 {
+                _mm512_storeu_epi32(chunk.as_mut_ptr() as *mut i32, r);
+            } else {
+                let mut buf = [0; 64];
+                _mm512_storeu_epi32(buf.as_mut_ptr() as *mut i32, r);
+                let l = chunk.len();
+                chunk.copy_from_slice(&buf[..l]);
+            }
         }
     }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> digest::zeroize::Zeroize for Avx512<I, R, F, H> {
+impl digest::zeroize::Zeroize for Avx512 {
     fn zeroize(&mut self) {
         use core::{ptr, sync::atomic::{self, Ordering}};
 
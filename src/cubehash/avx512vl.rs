@@ -0,0 +1,30 @@
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+use core::{iter, mem};
+
+use super::CubeHashBackend;
+use static_assertions::const_assert_eq;
+
+macro_rules! rotl_xmm {
+    ($v:expr, $l:literal, $r:literal) => { _mm_rol_epi32($v, $l) };
+}
+
+// the SSE2 code with EVEX rotates
+xmm_backend!(Avx512Vl, "avx512f,avx512vl", rotl_xmm);
+
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => { _mm256_rol_epi32($v, $l) };
+}
+
+// with 32 vector registers the 256-bit lane-sliced state no longer spills
+#[target_feature(enable = "avx512f,avx512vl")]
+pub(super) unsafe fn permute_lanes(x: &mut [[u32; 8]; 32], rounds: u16) {
+    let mut v: [__m256i; 32] = mem::transmute(*x);
+    for _ in 0..rounds {
+        lane_round!(v, _mm256_add_epi32, _mm256_xor_si256, rotl);
+    }
+    *x = mem::transmute::<[__m256i; 32], [[u32; 8]; 32]>(v);
+}
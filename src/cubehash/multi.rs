@@ -0,0 +1,146 @@
+use core::iter;
+
+// One CubeHash round over a lane-sliced state: x[k] holds word k of every
+// lane. The swaps are plain renames, so each backend only has to supply add,
+// xor and rotate for its vector type.
+macro_rules! lane_round {
+    ($x:ident, $add:path, $xor:path, $rotl:ident) => {{
+        let x = &mut $x;
+        for i in 0..16 { x[i + 16] = $add(x[i + 16], x[i]); }
+        let y = *x;
+        for i in 0..16 { x[i] = $rotl!(y[i ^ 8], 7, 25); }
+        for i in 0..16 { x[i] = $xor(x[i], x[i + 16]); }
+        let y = *x;
+        for i in 0..16 { x[i + 16] = y[(i ^ 2) + 16]; }
+        for i in 0..16 { x[i + 16] = $add(x[i + 16], x[i]); }
+        let y = *x;
+        for i in 0..16 { x[i] = $rotl!(y[i ^ 4], 11, 21); }
+        for i in 0..16 { x[i] = $xor(x[i], x[i + 16]); }
+        let y = *x;
+        for i in 0..16 { x[i + 16] = y[(i ^ 1) + 16]; }
+    }};
+}
+
+#[derive(Clone, Copy)]
+struct Lane {
+    msg: Option<usize>,
+    pos: usize,
+    padded: bool,
+    finalizing: bool,
+    remaining: u16
+}
+
+impl Lane {
+    const IDLE: Self = Self { msg: None, pos: 0, padded: false, finalizing: false, remaining: 0 };
+}
+
+struct Scheduler<'a, const N: usize, O> {
+    x: [[u32; N]; 32],
+    lanes: [Lane; N],
+    start: &'a [u32; 32],
+    b: usize,
+    r: u16,
+    f: u16,
+    inputs: &'a [&'a [u8]],
+    outputs: &'a mut [O],
+    queue: core::ops::Range<usize>
+}
+
+impl<const N: usize, O: AsMut<[u8]>> Scheduler<'_, N, O> {
+    // Handles the events of a lane that has run all its pending rounds:
+    // absorbing the next (possibly padding) block, starting finalization or
+    // emitting the digest. Idle lanes pick up the next queued message.
+    fn advance(&mut self, l: usize) {
+        loop {
+            let Self { x, lanes, start, b, r, f, inputs, outputs, queue } = self;
+            let lane = &mut lanes[l];
+
+            let Some(m) = lane.msg else {
+                let Some(m) = queue.next() else { return };
+                for (w, s) in iter::zip(x.iter_mut(), start.iter()) {
+                    w[l] = *s;
+                }
+                *lane = Lane { msg: Some(m), ..Lane::IDLE };
+                continue
+            };
+
+            if lane.remaining > 0 {
+                return;
+            }
+
+            if lane.finalizing {
+                for (k, chunk) in outputs[m].as_mut().chunks_mut(4).enumerate() {
+                    let n = chunk.len();
+                    chunk.copy_from_slice(&x[k][l].to_le_bytes()[..n]);
+                }
+                *lane = Lane::IDLE;
+            } else if lane.padded {
+                x[31][l] ^= 1;
+                lane.finalizing = true;
+                lane.remaining = *f;
+            } else {
+                let msg = inputs[m];
+                let block = &msg[lane.pos..(lane.pos + *b).min(msg.len())];
+                let mut words = block.chunks_exact(4);
+                for (k, word) in (&mut words).enumerate() {
+                    x[k][l] ^= u32::from_le_bytes(word.try_into().unwrap());
+                }
+
+                let tail = words.remainder();
+                let mut buf = [0; 4];
+                buf[..tail.len()].copy_from_slice(tail);
+                if block.len() < *b {
+                    buf[tail.len()] = 0x80;
+                    lane.padded = true;
+                }
+                if buf != [0; 4] {
+                    x[block.len() / 4][l] ^= u32::from_le_bytes(buf);
+                }
+                lane.pos += *b;
+                lane.remaining = *r;
+            }
+        }
+    }
+}
+
+// Hashes each input into the matching output, starting every message from
+// `start` and interleaving up to N messages at a time. Lanes run the same
+// number of rounds per permute call, so messages of different lengths are
+// handled by running until the nearest event of any busy lane.
+pub(super) fn hash_many<const N: usize, O: AsMut<[u8]>>(
+    start: &[u32; 32],
+    b: usize,
+    r: u16,
+    f: u16,
+    inputs: &[&[u8]],
+    outputs: &mut [O],
+    mut permute: impl FnMut(&mut [[u32; N]; 32], u16)
+) {
+    assert_eq!(inputs.len(), outputs.len(), "hash_many needs one output per input");
+
+    let mut s = Scheduler {
+        x: [[0; N]; 32],
+        lanes: [Lane::IDLE; N],
+        start,
+        b,
+        r,
+        f,
+        inputs,
+        outputs,
+        queue: 0..inputs.len()
+    };
+
+    for l in 0..N {
+        s.advance(l);
+    }
+
+    while let Some(k) = s.lanes.iter().filter(|lane| lane.msg.is_some()).map(|lane| lane.remaining).min() {
+        permute(&mut s.x, k);
+        for l in 0..N {
+            if s.lanes[l].msg.is_some() {
+                s.lanes[l].remaining -= k;
+                s.advance(l);
+            }
+        }
+    }
+}
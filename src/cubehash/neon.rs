@@ -1,13 +1,12 @@
-use core::{arch::aarch64::*, marker::PhantomData, iter, mem};
+use core::{arch::aarch64::*, iter, mem};
 
-use super::{CubeHashCore, CubeHashBackend};
-use digest::{core_api::BlockSizeUser, array::{Array, ArraySize}, typenum::{consts::{U0, U32, U64}, IsGreater, IsLessOrEqual, True, Unsigned}};
+use super::CubeHashBackend;
 use static_assertions::const_assert_eq;
 
-const_assert_eq!(<CubeHashCore::<16, 16, 32, U64> as BlockSizeUser>::BlockSize::USIZE, 2*mem::size_of::<uint32x4_t>());
+const_assert_eq!(mem::size_of::<Neon>(), 128);
 
 #[derive(Clone)]
-pub struct Neon<const I: u16, const R: u16, const F: u16, H> {
+pub struct Neon {
     r000: uint32x4_t,
     r001: uint32x4_t,
     r010: uint32x4_t,
@@ -15,11 +14,10 @@ pub struct Neon<const I: u16, const R: u16, const F: u16, H> {
     r100: uint32x4_t,
     r101: uint32x4_t,
     r110: uint32x4_t,
-    r111: uint32x4_t,
-    _phantom: PhantomData<H>
+    r111: uint32x4_t
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> Neon<I, R, F, H> {
+impl Neon {
     #[inline]
     #[target_feature(enable = "neon")]
     unsafe fn round(&mut self) {
@@ -116,60 +114,93 @@ impl<const I: u16, const R: u16, const F: u16, H> Neon<I, R, F, H> {
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> CubeHashBackend<I, R, F, H> for Neon<I, R, F, H> {
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => {{ let v = $v; vsriq_n_u32(vshlq_n_u32(v, $l), v, $r) }};
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn permute_lanes(x: &mut [[u32; 4]; 32], rounds: u16) {
+    let mut v: [uint32x4_t; 32] = mem::transmute(*x);
+    for _ in 0..rounds {
+        lane_round!(v, vaddq_u32, veorq_u32, rotl);
+    }
+    *x = mem::transmute::<[uint32x4_t; 32], [[u32; 4]; 32]>(v);
+}
+
+impl CubeHashBackend for Neon {
     #[inline]
     #[target_feature(enable = "neon")]
-    unsafe fn init() -> Self where H: Unsigned {
-        let mut init = Self {
-            r000: vld1q_u32([
-                H::U32,
-                <CubeHashCore<I, R, F, H> as BlockSizeUser>::BlockSize::U32,
-                R.into(),
-                0
-            ].as_ptr()),
-            r001: vmovq_n_u32(0),
-            r010: vmovq_n_u32(0),
-            r011: vmovq_n_u32(0),
-            r100: vmovq_n_u32(0),
-            r101: vmovq_n_u32(0),
-            r110: vmovq_n_u32(0),
-            r111: vmovq_n_u32(0),
-            _phantom: PhantomData
-        };
-        // the compiler precomputes this?? incredible
-        for _ in 0..I {
-            init.round();
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        let iv = iv.as_ptr();
+        Self {
+            r000: vld1q_u32(iv),
+            r001: vld1q_u32(iv.add(4)),
+            r010: vld1q_u32(iv.add(8)),
+            r011: vld1q_u32(iv.add(12)),
+            r100: vld1q_u32(iv.add(16)),
+            r101: vld1q_u32(iv.add(20)),
+            r110: vld1q_u32(iv.add(24)),
+            r111: vld1q_u32(iv.add(28))
         }
-        init
     }
 
     #[inline]
     #[target_feature(enable = "neon")]
-    unsafe fn update_block(&mut self, block: &Array<u8, U32>) {
-        let x000 = vld1q_u32(block.as_ptr() as *const u32);
-        let x001 = vld1q_u32(block[16..].as_ptr() as *const u32);
-        self.r000 = veorq_u32(self.r000, x000);
-        self.r001 = veorq_u32(self.r001, x001);
-        for _ in 0..R {
-            self.round();
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        // work on a local copy so the state stays in registers across blocks
+        let mut state = self.clone();
+
+        for block in blocks {
+            let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = &mut state;
+
+            for (reg, chunk) in iter::zip([r000, r001, r010, r011, r100, r101, r110, r111], block.chunks(16)) {
+                let x = if chunk.len() == 16 {
+                    vld1q_u32(chunk.as_ptr() as *const u32)
+                } else {
+                    let mut buf = [0; 16];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    vld1q_u32(buf.as_ptr() as *const u32)
+                };
+                *reg = veorq_u32(*reg, x);
+            }
+
+            for _ in 0..r {
+                state.round();
+            }
         }
+
+        *self = state;
     }
 
     #[inline]
     #[target_feature(enable = "neon")]
-    unsafe fn finalize(&mut self, out: &mut Array<u8, H>) where H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True> {
+    unsafe fn finalize(&mut self, f: u16) {
         self.r111 = veorq_u32(
             self.r111,
             vld1q_u32([0, 0, 0, 1].as_ptr())
         );
 
-        for _ in 0..F {
+        for _ in 0..f {
             self.round();
         }
+    }
+
+    #[inline]
+    #[target_feature(enable = "neon")]
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
+
+        for _ in 0..r {
+            self.round();
+        }
+    }
 
-        let &mut Self { r000, r001, r010, r011, .. } = self;
+    #[inline]
+    #[target_feature(enable = "neon")]
+    unsafe fn output(&self, out: &mut [u8]) {
+        let &Self { r000, r001, r010, r011, r100, r101, r110, r111 } = self;
 
-        for (chunk, r) in iter::zip(out.chunks_mut(16), [r000, r001, r010, r011]) {
+        for (chunk, r) in iter::zip(out.chunks_mut(16), [r000, r001, r010, r011, r100, r101, r110, r111]) {
             if chunk.len() == 16 {
                 vst1q_u32(chunk.as_mut_ptr() as *mut u32, r);
             } else {
@@ -192,7 +223,7 @@ fn zeroize_reg(r: &mut uint32x4_t) {
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> digest::zeroize::Zeroize for Neon<I, R, F, H> {
+impl digest::zeroize::Zeroize for Neon {
     fn zeroize(&mut self) {
         let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = self;
         zeroize_reg(r000);
@@ -0,0 +1,197 @@
+use core::{iter, mem, simd::{simd_swizzle, u32x4, u8x16, ToBytes}};
+
+use super::CubeHashBackend;
+use static_assertions::const_assert_eq;
+
+const_assert_eq!(mem::size_of::<Portable>(), 128);
+
+#[derive(Clone)]
+pub struct Portable {
+    r000: u32x4,
+    r001: u32x4,
+    r010: u32x4,
+    r011: u32x4,
+    r100: u32x4,
+    r101: u32x4,
+    r110: u32x4,
+    r111: u32x4
+}
+
+impl Portable {
+    #[inline]
+    fn round(&mut self) {
+        let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = *self;
+
+        // 1. add
+        let r100 = r100 + r000;
+        let r101 = r101 + r001;
+        let r110 = r110 + r010;
+        let r111 = r111 + r011;
+
+        // 2. rotate
+        let r000 = (r000 << 7) | (r000 >> 25);
+        let r001 = (r001 << 7) | (r001 >> 25);
+        let r010 = (r010 << 7) | (r010 >> 25);
+        let r011 = (r011 << 7) | (r011 >> 25);
+        
+        // 3. swap
+        let (r000, r010) = (r010, r000);
+        let (r001, r011) = (r011, r001);
+
+        // 4. xor
+        let r000 = r000 ^ r100;
+        let r001 = r001 ^ r101;
+        let r010 = r010 ^ r110;
+        let r011 = r011 ^ r111;
+
+        // 5. swap
+        let r100 = simd_swizzle!(r100, [2, 3, 0, 1]);
+        let r101 = simd_swizzle!(r101, [2, 3, 0, 1]);
+        let r110 = simd_swizzle!(r110, [2, 3, 0, 1]);
+        let r111 = simd_swizzle!(r111, [2, 3, 0, 1]);
+
+        // 6. add
+        let r100 = r100 + r000;
+        let r101 = r101 + r001;
+        let r110 = r110 + r010;
+        let r111 = r111 + r011;
+
+        // 7. rotate
+        let r000 = (r000 << 11) | (r000 >> 21);
+        let r001 = (r001 << 11) | (r001 >> 21);
+        let r010 = (r010 << 11) | (r010 >> 21);
+        let r011 = (r011 << 11) | (r011 >> 21);
+
+        // 8. swap
+        let (r000, r001) = (r001, r000);
+        let (r010, r011) = (r011, r010);
+
+        // 9. xor
+        let r000 = r000 ^ r100;
+        let r001 = r001 ^ r101;
+        let r010 = r010 ^ r110;
+        let r011 = r011 ^ r111;
+
+        // 10. swap
+        let r100 = simd_swizzle!(r100, [1, 0, 3, 2]);
+        let r101 = simd_swizzle!(r101, [1, 0, 3, 2]);
+        let r110 = simd_swizzle!(r110, [1, 0, 3, 2]);
+        let r111 = simd_swizzle!(r111, [1, 0, 3, 2]);
+
+        self.r000 = r000;
+        self.r001 = r001;
+        self.r010 = r010;
+        self.r011 = r011;
+        self.r100 = r100;
+        self.r101 = r101;
+        self.r110 = r110;
+        self.r111 = r111;
+    }
+}
+
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => {{ let v = $v; (v << $l) | (v >> $r) }};
+}
+
+fn add(a: u32x4, b: u32x4) -> u32x4 {
+    a + b
+}
+
+fn xor(a: u32x4, b: u32x4) -> u32x4 {
+    a ^ b
+}
+
+pub(super) fn permute_lanes(x: &mut [[u32; 4]; 32], rounds: u16) {
+    let mut v = x.map(u32x4::from_array);
+    for _ in 0..rounds {
+        lane_round!(v, add, xor, rotl);
+    }
+    *x = v.map(u32x4::to_array);
+}
+
+impl CubeHashBackend for Portable {
+    #[inline]
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        let [r000, r001, r010, r011, r100, r101, r110, r111] = core::array::from_fn(|i| u32x4::from_slice(&iv[4*i..]));
+        Self { r000, r001, r010, r011, r100, r101, r110, r111 }
+    }
+
+    #[inline]
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        // work on a local copy so the state stays in registers across blocks
+        let mut state = self.clone();
+
+        for block in blocks {
+            let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = &mut state;
+
+            for (reg, chunk) in iter::zip([r000, r001, r010, r011, r100, r101, r110, r111], block.chunks(16)) {
+                let x = if chunk.len() == 16 {
+                    u8x16::from_slice(chunk)
+                } else {
+                    let mut buf = [0; 16];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    u8x16::from_array(buf)
+                };
+                *reg ^= u32x4::from_le_bytes(x);
+            }
+
+            for _ in 0..r {
+                state.round();
+            }
+        }
+
+        *self = state;
+    }
+
+    #[inline]
+    unsafe fn finalize(&mut self, f: u16) {
+        self.r111 ^= u32x4::from_array([0, 0, 0, 1]);
+
+        for _ in 0..f {
+            self.round();
+        }
+    }
+
+    #[inline]
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
+
+        for _ in 0..r {
+            self.round();
+        }
+    }
+
+    #[inline]
+    unsafe fn output(&self, out: &mut [u8]) {
+        let &Self { r000, r001, r010, r011, r100, r101, r110, r111 } = self;
+
+        for (chunk, r) in iter::zip(out.chunks_mut(16), [r000, r001, r010, r011, r100, r101, r110, r111]) {
+            let l = chunk.len();
+            chunk.copy_from_slice(&r.to_le_bytes().as_array()[..l]);
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+#[inline(always)]
+fn zeroize_reg(r: &mut u32x4) {
+    use core::{ptr, sync::atomic::{self, Ordering}};
+
+    unsafe { ptr::write_volatile(r, u32x4::splat(0)) };
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(feature = "zeroize")]
+impl digest::zeroize::Zeroize for Portable {
+    fn zeroize(&mut self) {
+        let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = self;
+        zeroize_reg(r000);
+        zeroize_reg(r001);
+        zeroize_reg(r010);
+        zeroize_reg(r011);
+        zeroize_reg(r100);
+        zeroize_reg(r101);
+        zeroize_reg(r110);
+        zeroize_reg(r111);
+    }
+}
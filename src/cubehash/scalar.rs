@@ -0,0 +1,113 @@
+use core::iter;
+
+use super::CubeHashBackend;
+
+#[derive(Clone)]
+pub struct Scalar {
+    r: [u32; 32]
+}
+
+/*
+ * The swaps are never performed. After one round the low half is left
+ * permuted by i ^ 12 and the high half by i ^ 3, so two rounds bring the
+ * layout back to where it started. What changes from round to round is which
+ * high word each low word is paired with in the add and xor steps; the masks
+ * below are those pairings for the first and second round of a pair.
+ *
+ * x[j] is paired with x[16 + (j ^ M)]; everything indexes by constants, so
+ * the whole state ends up in registers.
+ */
+#[inline(always)]
+fn round<const M1: usize, const M2: usize, const M3: usize, const M4: usize>(x: &mut [u32; 32]) {
+    for j in 0..16 { x[16 + (j ^ M1)] = x[16 + (j ^ M1)].wrapping_add(x[j]); }
+    for w in &mut x[..16] { *w = w.rotate_left(7); }
+    for j in 0..16 { x[j] ^= x[16 + (j ^ M2)]; }
+    for j in 0..16 { x[16 + (j ^ M3)] = x[16 + (j ^ M3)].wrapping_add(x[j]); }
+    for w in &mut x[..16] { *w = w.rotate_left(11); }
+    for j in 0..16 { x[j] ^= x[16 + (j ^ M4)]; }
+}
+
+#[inline(always)]
+fn rounds(x: &mut [u32; 32], n: u16) {
+    for _ in 0..n / 2 {
+        round::<0, 8, 10, 14>(x);
+        round::<15, 7, 5, 1>(x);
+    }
+
+    if n % 2 == 1 {
+        round::<0, 8, 10, 14>(x);
+        let y = *x;
+        for i in 0..16 {
+            x[i] = y[i ^ 12];
+            x[16 + i] = y[16 + (i ^ 3)];
+        }
+    }
+}
+
+pub(super) fn permute_lanes(x: &mut [[u32; 1]; 32], n: u16) {
+    let mut v = x.map(|[w]| w);
+    rounds(&mut v, n);
+    *x = v.map(|w| [w]);
+}
+
+impl Scalar {
+    #[inline]
+    fn rounds(&mut self, n: u16) {
+        let mut x = self.r;
+        rounds(&mut x, n);
+        self.r = x;
+    }
+}
+
+impl CubeHashBackend for Scalar {
+    #[inline]
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        Self { r: *iv }
+    }
+
+    #[inline]
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        for block in blocks {
+            let mut words = block.chunks_exact(4);
+            for (word, chunk) in iter::zip(self.r.iter_mut(), &mut words) {
+                *word ^= u32::from_le_bytes(chunk.try_into().unwrap());
+            }
+
+            let tail = words.remainder();
+            if !tail.is_empty() {
+                let mut buf = [0; 4];
+                buf[..tail.len()].copy_from_slice(tail);
+                self.r[block.len() / 4] ^= u32::from_le_bytes(buf);
+            }
+
+            self.rounds(r);
+        }
+    }
+
+    #[inline]
+    unsafe fn finalize(&mut self, f: u16) {
+        self.r[31] ^= 1;
+        self.rounds(f);
+    }
+
+    #[inline]
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
+        self.rounds(r);
+    }
+
+    #[inline]
+    unsafe fn output(&self, out: &mut [u8]) {
+        for (chunk, word) in iter::zip(out.chunks_mut(4), self.r) {
+            let l = chunk.len();
+            chunk.copy_from_slice(&word.to_le_bytes()[..l]);
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl digest::zeroize::Zeroize for Scalar {
+    fn zeroize(&mut self) {
+        self.r.zeroize();
+    }
+}
@@ -0,0 +1,53 @@
+use core::{iter, sync::atomic::{AtomicU8, Ordering}};
+
+use super::{iv, kat::KATS, Backend, BackendError, BackendSelector};
+
+fn run(backend: BackendSelector) -> Result<bool, BackendError> {
+    for (i, r, b, f, msg, expected) in KATS {
+        let mut state = Backend::new_with_backend(backend, &iv(expected.len() as u16, b, r, i))?;
+        let b = b as usize;
+
+        let blocks = msg.chunks_exact(b);
+        let tail = blocks.remainder();
+        let mut last = [0; 128];
+        last[..tail.len()].copy_from_slice(tail);
+        last[tail.len()] = 0x80;
+        state.update_blocks(blocks.chain(iter::once(&last[..b])), r);
+        state.finalize(f);
+
+        let mut out = [0; 64];
+        state.output(&mut out[..expected.len()]);
+        if out[..expected.len()] != *expected {
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+const UNTESTED: u8 = 0;
+const PASSED: u8 = 1;
+const FAILED: u8 = 2;
+
+// indexed like BackendSelector::ALL
+static RESULTS: [AtomicU8; 8] = [const { AtomicU8::new(UNTESTED) }; 8];
+
+// Runs the known-answer tests through a backend the first time it's asked
+// about and remembers the outcome. Default falls back to Soft for any backend
+// that fails.
+pub fn self_test(backend: BackendSelector) -> Result<bool, BackendError> {
+    let result = &RESULTS[backend.index()];
+    match result.load(Ordering::Relaxed) {
+        PASSED => Ok(true),
+        FAILED => Ok(false),
+        _ => {
+            let passed = run(backend)?;
+            result.store(if passed { PASSED } else { FAILED }, Ordering::Relaxed);
+            Ok(passed)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+pub(super) fn mark_failed(backend: BackendSelector, failed: bool) {
+    RESULTS[backend.index()].store(if failed { FAILED } else { UNTESTED }, Ordering::Relaxed);
+}
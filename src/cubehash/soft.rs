@@ -1,101 +1,186 @@
-use core::{iter, marker::PhantomData};
+use core::iter;
 
-use super::{CubeHashCore, CubeHashBackend};
-use digest::{array::{Array, ArraySize}, consts::U32, core_api::BlockSizeUser, typenum::{consts::{U0, U64}, IsGreater, IsLessOrEqual, True, Unsigned}};
+use super::CubeHashBackend;
 
 #[derive(Clone)]
-pub struct Soft<const I: u16, const R: u16, const F: u16, H> {
-    r: [u32; 32],
-    _phantom: PhantomData<H>
+pub struct Soft {
+    r: [u32; 32]
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> Soft<I, R, F, H> {
-    #[inline]
-    fn round(&mut self) {
-        // Direct calque of the eBASH simple version
-        let Self { r, .. } = self;
-        let mut tmp = [0; 16];
+// Direct calque of the eBASH simple version, written to be usable in const
+// contexts
+const fn round(r: &mut [u32; 32]) {
+    let mut tmp = [0; 16];
+    let mut i;
 
-        for i in 0..16 {
-            r[i+16] = r[i+16].wrapping_add(r[i]);
-        }
-        for i in 0..16 {
-            tmp[i ^ 0b1000] = r[i];
-        }
-        for i in 0..16 {
-            r[i] = tmp[i].rotate_left(7);
-        }
-        for i in 0..16 {
-            r[i] ^= r[i+16];
-        }
-        for i in 0..16 {
-            tmp[i ^ 0b10] = r[i | 0b10000];
-        }
-        r[16..].copy_from_slice(&tmp);
-        for i in 0..16 {
-            r[i+16] = r[i+16].wrapping_add(r[i]);
-        }
-        for i in 0..16 {
-            tmp[i ^ 0b100] = r[i];
-        }
-        for i in 0..16 {
-            r[i] = tmp[i].rotate_left(11);
-        }
-        for i in 0..16 {
-            r[i] ^= r[i+16];
-        }
-        for i in 0..16 {
-            tmp[i ^ 0b1] = r[i | 0b10000];
+    i = 0; while i < 16 { r[i+16] = r[i+16].wrapping_add(r[i]); i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b1000] = r[i]; i += 1; }
+    i = 0; while i < 16 { r[i] = tmp[i].rotate_left(7); i += 1; }
+    i = 0; while i < 16 { r[i] ^= r[i+16]; i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b10] = r[i | 0b10000]; i += 1; }
+    i = 0; while i < 16 { r[i+16] = tmp[i]; i += 1; }
+    i = 0; while i < 16 { r[i+16] = r[i+16].wrapping_add(r[i]); i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b100] = r[i]; i += 1; }
+    i = 0; while i < 16 { r[i] = tmp[i].rotate_left(11); i += 1; }
+    i = 0; while i < 16 { r[i] ^= r[i+16]; i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b1] = r[i | 0b10000]; i += 1; }
+    i = 0; while i < 16 { r[i+16] = tmp[i]; i += 1; }
+}
+
+// round with the steps undone in reverse order
+const fn inv_round(r: &mut [u32; 32]) {
+    let mut tmp = [0; 16];
+    let mut i;
+
+    i = 0; while i < 16 { tmp[i ^ 0b1] = r[i | 0b10000]; i += 1; }
+    i = 0; while i < 16 { r[i+16] = tmp[i]; i += 1; }
+    i = 0; while i < 16 { r[i] ^= r[i+16]; i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b100] = r[i]; i += 1; }
+    i = 0; while i < 16 { r[i] = tmp[i].rotate_right(11); i += 1; }
+    i = 0; while i < 16 { r[i+16] = r[i+16].wrapping_sub(r[i]); i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b10] = r[i | 0b10000]; i += 1; }
+    i = 0; while i < 16 { r[i+16] = tmp[i]; i += 1; }
+    i = 0; while i < 16 { r[i] ^= r[i+16]; i += 1; }
+    i = 0; while i < 16 { tmp[i ^ 0b1000] = r[i]; i += 1; }
+    i = 0; while i < 16 { r[i] = tmp[i].rotate_right(7); i += 1; }
+    i = 0; while i < 16 { r[i+16] = r[i+16].wrapping_sub(r[i]); i += 1; }
+}
+
+// h and b are in bytes
+pub(crate) const fn iv(h: u16, b: u16, r: u16, i: u16) -> [u32; 32] {
+    let mut state = [0; 32];
+    state[0] = h as u32;
+    state[1] = b as u32;
+    state[2] = r as u32;
+
+    let mut n = 0;
+    while n < i {
+        round(&mut state);
+        n += 1;
+    }
+    state
+}
+
+// Byte-at-a-time CubeHashi+r/b+f-h for const contexts; h and b are in bytes
+pub(crate) const fn digest<const H: usize>(i: u16, r: u16, b: u16, f: u16, data: &[u8]) -> [u8; H] {
+    let mut state = iv(H as u16, b, r, i);
+    let b = b as usize;
+    let mut n;
+
+    // the final block is padded with 0x80 at data.len()
+    let mut pos = 0;
+    while pos <= data.len() {
+        let byte = if pos < data.len() { data[pos] } else { 0x80 };
+        let k = pos % b;
+        state[k / 4] ^= (byte as u32) << (8 * (k % 4));
+        pos += 1;
+
+        if k == b - 1 || pos > data.len() {
+            n = 0;
+            while n < r { round(&mut state); n += 1; }
         }
-        r[16..].copy_from_slice(&tmp);
     }
+
+    state[31] ^= 1;
+    n = 0;
+    while n < f { round(&mut state); n += 1; }
+
+    let mut out = [0; H];
+    let mut k = 0;
+    while k < H {
+        out[k] = (state[k / 4] >> (8 * (k % 4))) as u8;
+        k += 1;
+    }
+    out
+}
+
+fn add<const N: usize>(a: [u32; N], b: [u32; N]) -> [u32; N] {
+    core::array::from_fn(|i| a[i].wrapping_add(b[i]))
+}
+
+fn xor<const N: usize>(a: [u32; N], b: [u32; N]) -> [u32; N] {
+    core::array::from_fn(|i| a[i] ^ b[i])
+}
+
+macro_rules! rotl {
+    ($v:expr, $l:literal, $r:literal) => { $v.map(|w| w.rotate_left($l)) };
+}
+
+pub(super) fn permute_lanes<const N: usize>(x: &mut [[u32; N]; 32], rounds: u16) {
+    let mut v = *x;
+    for _ in 0..rounds {
+        lane_round!(v, add, xor, rotl);
+    }
+    *x = v;
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> CubeHashBackend<I, R, F, H> for Soft<I, R, F, H> {
+impl Soft {
     #[inline]
-    unsafe fn init() -> Self where H: Unsigned {
-        let mut init = Self {
-            r: [
-                H::U32,
-                <CubeHashCore<I, R, F, H> as BlockSizeUser>::BlockSize::U32,
-                R.into(), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
-            ],
-            _phantom: PhantomData
-        };
-        for _ in 0..I {
-            init.round();
-        }
-        init
+    fn round(&mut self) {
+        round(&mut self.r);
+    }
+}
+
+impl CubeHashBackend for Soft {
+    #[inline]
+    unsafe fn init(iv: &[u32; 32]) -> Self {
+        Self { r: *iv }
     }
 
     #[inline]
-    unsafe fn update_block(&mut self, block: &Array<u8, U32>) {
-        for (word, chunk) in iter::zip(self.r.iter_mut(), block.chunks_exact(4)) {
-            *word ^= u32::from_le_bytes(chunk.try_into().unwrap())
+    unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+        for block in blocks {
+            for (word, chunk) in iter::zip(self.r.iter_mut(), block.chunks(4)) {
+                if let Ok(chunk) = chunk.try_into() {
+                    *word ^= u32::from_le_bytes(chunk)
+                } else {
+                    let mut buf = [0; 4];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    *word ^= u32::from_le_bytes(buf)
+                }
+            }
+
+            for _ in 0..r {
+                self.round();
+            }
         }
+    }
 
-        for _ in 0..R {
+    #[inline]
+    unsafe fn finalize(&mut self, f: u16) {
+        self.r[31] ^= 1;
+
+        for _ in 0..f {
             self.round();
         }
     }
 
     #[inline]
-    unsafe fn finalize(&mut self, out: &mut Array<u8, H>) where H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True> {
-        self.r[31] ^= 1;
+    unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+        self.output(out);
 
-        for _ in 0..F {
+        for _ in 0..r {
             self.round();
         }
+    }
 
+    #[inline]
+    unsafe fn output(&self, out: &mut [u8]) {
         for (chunk, word) in iter::zip(out.iter_mut(), self.r.iter().flat_map(|x| x.to_le_bytes())) {
             *chunk = word;
         }
     }
+
+    #[inline]
+    unsafe fn unpermute(&mut self, rounds: u16) {
+        for _ in 0..rounds {
+            inv_round(&mut self.r);
+        }
+    }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> digest::zeroize::Zeroize for Soft<I, R, F, H> {
+impl digest::zeroize::Zeroize for Soft {
     fn zeroize(&mut self) {
         self.r.zeroize();
     }
@@ -0,0 +1,195 @@
+// The SSE2 backend, eight 128-bit registers. AVX512VL reuses it with EVEX
+// rotates, so the round and the load/store code are written once here and
+// each backend supplies its name, target features and a rotate-left macro
+// taking (value, left, right).
+macro_rules! xmm_backend {
+    ($name:ident, $features:literal, $rotl:ident) => {
+        const_assert_eq!(mem::size_of::<$name>(), 128);
+
+        #[derive(Clone)]
+        pub struct $name {
+            r000: __m128i,
+            r001: __m128i,
+            r010: __m128i,
+            r011: __m128i,
+            r100: __m128i,
+            r101: __m128i,
+            r110: __m128i,
+            r111: __m128i
+        }
+
+        impl $name {
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn round(&mut self) {
+                let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = *self;
+
+                // 1. add
+                let r100 = _mm_add_epi32(r100, r000);
+                let r101 = _mm_add_epi32(r101, r001);
+                let r110 = _mm_add_epi32(r110, r010);
+                let r111 = _mm_add_epi32(r111, r011);
+
+                // 2. rotate
+                let r000 = $rotl!(r000, 7, 25);
+                let r001 = $rotl!(r001, 7, 25);
+                let r010 = $rotl!(r010, 7, 25);
+                let r011 = $rotl!(r011, 7, 25);
+
+                // 3. swap
+                let (r000, r010) = (r010, r000);
+                let (r001, r011) = (r011, r001);
+
+                // 4. xor
+                let r000 = _mm_xor_si128(r000, r100);
+                let r001 = _mm_xor_si128(r001, r101);
+                let r010 = _mm_xor_si128(r010, r110);
+                let r011 = _mm_xor_si128(r011, r111);
+
+                // 5. swap
+                let r100 = _mm_shuffle_epi32(r100, 0x4E);
+                let r101 = _mm_shuffle_epi32(r101, 0x4E);
+                let r110 = _mm_shuffle_epi32(r110, 0x4E);
+                let r111 = _mm_shuffle_epi32(r111, 0x4E);
+
+                // 6. add
+                let r100 = _mm_add_epi32(r100, r000);
+                let r101 = _mm_add_epi32(r101, r001);
+                let r110 = _mm_add_epi32(r110, r010);
+                let r111 = _mm_add_epi32(r111, r011);
+
+                // 7. rotate
+                let r000 = $rotl!(r000, 11, 21);
+                let r001 = $rotl!(r001, 11, 21);
+                let r010 = $rotl!(r010, 11, 21);
+                let r011 = $rotl!(r011, 11, 21);
+
+                // 8. swap
+                let (r000, r001) = (r001, r000);
+                let (r010, r011) = (r011, r010);
+
+                // 9. xor
+                let r000 = _mm_xor_si128(r000, r100);
+                let r001 = _mm_xor_si128(r001, r101);
+                let r010 = _mm_xor_si128(r010, r110);
+                let r011 = _mm_xor_si128(r011, r111);
+
+                // 10. swap
+                let r100 = _mm_shuffle_epi32(r100, 0xB1);
+                let r101 = _mm_shuffle_epi32(r101, 0xB1);
+                let r110 = _mm_shuffle_epi32(r110, 0xB1);
+                let r111 = _mm_shuffle_epi32(r111, 0xB1);
+
+                self.r000 = r000;
+                self.r001 = r001;
+                self.r010 = r010;
+                self.r011 = r011;
+                self.r100 = r100;
+                self.r101 = r101;
+                self.r110 = r110;
+                self.r111 = r111;
+            }
+        }
+
+        impl CubeHashBackend for $name {
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn init(iv: &[u32; 32]) -> Self {
+                let iv = iv.as_ptr() as *const __m128i;
+                Self {
+                    r000: _mm_loadu_si128(iv),
+                    r001: _mm_loadu_si128(iv.add(1)),
+                    r010: _mm_loadu_si128(iv.add(2)),
+                    r011: _mm_loadu_si128(iv.add(3)),
+                    r100: _mm_loadu_si128(iv.add(4)),
+                    r101: _mm_loadu_si128(iv.add(5)),
+                    r110: _mm_loadu_si128(iv.add(6)),
+                    r111: _mm_loadu_si128(iv.add(7))
+                }
+            }
+
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn update_blocks<'a>(&mut self, blocks: impl IntoIterator<Item = &'a [u8]>, r: u16) {
+                // work on a local copy so the state stays in registers across blocks
+                let mut state = self.clone();
+
+                for block in blocks {
+                    let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = &mut state;
+
+                    for (reg, chunk) in iter::zip([r000, r001, r010, r011, r100, r101, r110, r111], block.chunks(16)) {
+                        let x = if chunk.len() == 16 {
+                            _mm_loadu_si128(chunk.as_ptr() as *const __m128i)
+                        } else {
+                            let mut buf = [0; 16];
+                            buf[..chunk.len()].copy_from_slice(chunk);
+                            _mm_loadu_si128(buf.as_ptr() as *const __m128i)
+                        };
+                        *reg = _mm_xor_si128(*reg, x);
+                    }
+
+                    for _ in 0..r {
+                        state.round();
+                    }
+                }
+
+                *self = state;
+            }
+
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn finalize(&mut self, f: u16) {
+                self.r111 = _mm_xor_si128(
+                    self.r111,
+                    _mm_setr_epi32(0, 0, 0, 1)
+                );
+
+                for _ in 0..f {
+                    self.round();
+                }
+            }
+
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn squeeze(&mut self, out: &mut [u8], r: u16) {
+                self.output(out);
+
+                for _ in 0..r {
+                    self.round();
+                }
+            }
+
+            #[inline]
+            #[target_feature(enable = $features)]
+            unsafe fn output(&self, out: &mut [u8]) {
+                let &Self { r000, r001, r010, r011, r100, r101, r110, r111 } = self;
+
+                for (chunk, r) in iter::zip(out.chunks_mut(16), [r000, r001, r010, r011, r100, r101, r110, r111]) {
+                    if chunk.len() == 16 {
+                        _mm_storeu_si128(chunk.as_mut_ptr() as *mut __m128i, r);
+                    } else {
+                        let mut buf = [0; 16];
+                        _mm_storeu_si128(buf.as_mut_ptr() as *mut __m128i, r);
+                        let l = chunk.len();
+                        chunk.copy_from_slice(&buf[..l]);
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "zeroize")]
+        impl digest::zeroize::Zeroize for $name {
+            fn zeroize(&mut self) {
+                let Self { r000, r001, r010, r011, r100, r101, r110, r111, .. } = self;
+                r000.zeroize();
+                r001.zeroize();
+                r010.zeroize();
+                r011.zeroize();
+                r100.zeroize();
+                r101.zeroize();
+                r110.zeroize();
+                r111.zeroize();
+            }
+        }
+    };
+}
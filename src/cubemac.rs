@@ -1,58 +1,137 @@
 use core::slice;
 
-use digest::{block_buffer::Eager, core_api::{AlgorithmName, Block, BlockSizeUser, BufferKindUser, FixedOutputCore, UpdateCore}, crypto_common::KeySizeUser, array::ArraySize, typenum::{IsGreater, IsLessOrEqual, True, Unsigned, U0, U64}, KeyInit, MacMarker, OutputSizeUser};
+use digest::{block_buffer::Eager, core_api::{AlgorithmName, Block, BlockSizeUser, BufferKindUser, FixedOutputCore, UpdateCore}, crypto_common::{hazmat::{DeserializeStateError, SerializableState, SerializedState}, BlockSizes, KeySizeUser}, InvalidLength, array::ArraySize, typenum::{IsGreater, IsLessOrEqual, True, Unsigned, U0, U64, U128, U256, U384}, KeyInit, MacMarker, OutputSizeUser, Reset};
 
-use super::cubehash::CubeHashCore;
+#[cfg(feature = "selectable-backend")]
+use super::cubehash::BackendError;
+use super::cubehash::{BackendSelector, CubeHashCore};
 
+// keyed is the state right after absorbing the key, which reset goes back to
 #[derive(Clone)]
-pub struct CubeMacCore<const I: u16, const R: u16, const F: u16, H>(CubeHashCore<I, R, F, H>);
+pub struct CubeMacCore<const I: u16, const R: u16, B, const F: u16, H> {
+    state: CubeHashCore<I, R, B, F, H>,
+    keyed: CubeHashCore<I, R, B, F, H>
+}
 
-impl<const I: u16, const R: u16, const F: u16, H> MacMarker for CubeMacCore<I, R, F, H> {}
+impl<const I: u16, const R: u16, B, const F: u16, H> MacMarker for CubeMacCore<I, R, B, F, H> {}
 
-impl<const I: u16, const R: u16, const F: u16, H: Unsigned> AlgorithmName for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> AlgorithmName for CubeMacCore<I, R, B, F, H> {
     fn write_alg_name(f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "CubeMac<{}, {}, {}, {}>", I, R, F, H::USIZE)
+        write!(f, "CubeMac{}+{}/{}+{}-{}", I, R, B::USIZE, F, H::USIZE * 8)
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> BlockSizeUser for CubeMacCore<I, R, F, H> {
-    type BlockSize = <CubeHashCore<I, R, F, H> as BlockSizeUser>::BlockSize;
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> BlockSizeUser for CubeMacCore<I, R, B, F, H> {
+    type BlockSize = <CubeHashCore<I, R, B, F, H> as BlockSizeUser>::BlockSize;
 }
 
-impl<const I: u16, const R: u16, const F: u16, H: ArraySize> OutputSizeUser for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B, const F: u16, H: ArraySize> OutputSizeUser for CubeMacCore<I, R, B, F, H> {
     type OutputSize = H;
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> BufferKindUser for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> BufferKindUser for CubeMacCore<I, R, B, F, H> {
     type BufferKind = Eager;
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> KeySizeUser for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B, const F: u16, H> KeySizeUser for CubeMacCore<I, R, B, F, H> {
     type KeySize = U64;
 }
 
-impl<const I: u16, const R: u16, const F: u16, H: Unsigned> KeyInit for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: Unsigned> KeyInit for CubeMacCore<I, R, B, F, H> {
     #[inline]
     fn new(key: &digest::Key<Self>) -> Self {
-        let mut init = CubeHashCore::default();
-        let (low, high) = key.split_ref();
-        init.update_blocks(slice::from_ref(low));
-        init.update_blocks(slice::from_ref(high));
-        Self(init)
+        Self::with_core(CubeHashCore::default(), key)
+    }
+
+    // any length works, see with_core
+    #[inline]
+    fn new_from_slice(key: &[u8]) -> Result<Self, InvalidLength> {
+        Ok(Self::with_core(CubeHashCore::default(), key))
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: Unsigned> CubeMacCore<I, R, B, F, H> {
+    fn with_core(mut init: CubeHashCore<I, R, B, F, H>, key: &[u8]) -> Self {
+        // A 64-byte key is absorbed as is, so when b divides 64 (e.g. the
+        // default 32) the MAC is H(key || message); otherwise the last key
+        // block is zero-padded. Any other length gets 10* padding to whole
+        // blocks, plus a zero block if that comes out as long as a 64-byte
+        // key, so no two keys absorb the same blocks.
+        let b = B::USIZE;
+        let blocks = key.chunks_exact(b);
+        let tail = blocks.remainder();
+        for chunk in blocks {
+            init.update_blocks(slice::from_ref(&Block::<Self>::try_from(chunk).unwrap()));
+        }
+
+        let mut block = Block::<Self>::default();
+        block[..tail.len()].copy_from_slice(tail);
+        if key.len() == 64 {
+            if !tail.is_empty() {
+                init.update_blocks(slice::from_ref(&block));
+            }
+        } else {
+            block[tail.len()] = 0x80;
+            init.update_blocks(slice::from_ref(&block));
+            if key.len() - tail.len() + b == 64usize.next_multiple_of(b) {
+                init.update_blocks(slice::from_ref(&Block::<Self>::default()));
+            }
+        }
+
+        Self { state: init.clone(), keyed: init }
+    }
+
+    #[cfg(feature = "selectable-backend")]
+    pub fn new_with_backend(key: &[u8], backend: BackendSelector) -> Result<Self, BackendError> {
+        Ok(Self::with_core(CubeHashCore::new_with_backend(backend)?, key))
+    }
+
+    pub fn backend(&self) -> BackendSelector {
+        self.state.backend()
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H> UpdateCore for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H> UpdateCore for CubeMacCore<I, R, B, F, H> {
     #[inline(always)]
     fn update_blocks(&mut self, blocks: &[Block<Self>]) {
-        self.0.update_blocks(blocks)
+        self.state.update_blocks(blocks)
     }
 }
 
-impl<const I: u16, const R: u16, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> FixedOutputCore for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> FixedOutputCore for CubeMacCore<I, R, B, F, H> {
     #[inline(always)]
     fn finalize_fixed_core(&mut self, buffer: &mut digest::core_api::Buffer<Self>, out: &mut digest::Output<Self>) {
-        self.0.finalize_fixed_core(buffer, out)
+        self.state.finalize_fixed_core(buffer, out)
+    }
+}
+
+impl<const I: u16, const R: u16, B: BlockSizes + IsLessOrEqual<U128, Output = True>, const F: u16, H: ArraySize + IsGreater<U0, Output = True> + IsLessOrEqual<U64, Output = True>> CubeMacCore<I, R, B, F, H> {
+    // Computes the tag of each input under this key, interleaving independent
+    // messages across SIMD lanes
+    pub fn mac_many(&self, inputs: &[&[u8]], outputs: &mut [digest::Output<Self>]) {
+        self.keyed.hash_many(inputs, outputs)
+    }
+}
+
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> Reset for CubeMacCore<I, R, B, F, H> {
+    fn reset(&mut self) {
+        self.state = self.keyed.clone();
+    }
+}
+
+// the current state followed by the words of the post-key state, so a restored
+// MAC can still be reset; both started from the same IV, which is stored once
+impl<const I: u16, const R: u16, B: Unsigned, const F: u16, H: Unsigned> SerializableState for CubeMacCore<I, R, B, F, H> {
+    type SerializedStateSize = U384;
+
+    fn serialize(&self) -> SerializedState<Self> {
+        self.state.serialize().concat(self.keyed.serialize().split::<U128>().0)
+    }
+
+    fn deserialize(serialized_state: &SerializedState<Self>) -> Result<Self, DeserializeStateError> {
+        let (state, keyed) = serialized_state.split_ref::<U256>();
+        let iv = state.split_ref::<U128>().1;
+        Ok(Self { state: CubeHashCore::deserialize(state)?, keyed: CubeHashCore::deserialize(&(*keyed).concat(*iv))? })
     }
 }
 
@@ -60,11 +139,12 @@ impl<const I: u16, const R: u16, const F: u16, H: ArraySize + IsGreater<U0, Outp
 use digest::zeroize::{Zeroize, ZeroizeOnDrop};
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> Zeroize for CubeMacCore<I, R, F, H> {
+impl<const I: u16, const R: u16, B, const F: u16, H> Zeroize for CubeMacCore<I, R, B, F, H> {
     fn zeroize(&mut self) {
-        self.0.zeroize();
+        self.state.zeroize();
+        self.keyed.zeroize();
     }
 }
 
 #[cfg(feature = "zeroize")]
-impl<const I: u16, const R: u16, const F: u16, H> ZeroizeOnDrop for CubeMacCore<I, R, F, H> {}
\ No newline at end of file
+impl<const I: u16, const R: u16, B, const F: u16, H> ZeroizeOnDrop for CubeMacCore<I, R, B, F, H> {}
\ No newline at end of file
@@ -0,0 +1,248 @@
+use core::{fmt, str::FromStr};
+
+#[cfg(feature = "std")]
+use std::boxed::Box;
+
+use digest::{HashMarker, InvalidBufferSize, InvalidOutputSize, Reset, Update, VariableOutput, VariableOutputReset};
+
+#[cfg(feature = "selectable-backend")]
+use super::cubehash::BackendError;
+use super::cubehash::{iv, Backend, BackendSelector};
+
+/// CubeHash parameters in the spec's `CubeHashi+r/b+f-h` notation: `b` is in
+/// bytes and `h` is in bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CubeHashParams {
+    pub i: u16,
+    pub r: u16,
+    pub b: u16,
+    pub f: u16,
+    pub h: u16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamsError {
+    InitialRounds,
+    Rounds,
+    BlockSize,
+    FinalRounds,
+    OutputSize,
+    Syntax
+}
+
+impl CubeHashParams {
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        match *self {
+            Self { i: 0, .. } => Err(ParamsError::InitialRounds),
+            Self { r: 0, .. } => Err(ParamsError::Rounds),
+            Self { b: 0 | 129.., .. } => Err(ParamsError::BlockSize),
+            Self { f: 0, .. } => Err(ParamsError::FinalRounds),
+            Self { h, .. } if h == 0 || h > 512 || h % 8 != 0 => Err(ParamsError::OutputSize),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Default for CubeHashParams {
+    fn default() -> Self {
+        Self { i: 16, r: 16, b: 32, f: 32, h: 512 }
+    }
+}
+
+impl fmt::Display for CubeHashParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CubeHash{}+{}/{}+{}-{}", self.i, self.r, self.b, self.f, self.h)
+    }
+}
+
+impl FromStr for CubeHashParams {
+    type Err = ParamsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |s: &str| s.parse().map_err(|_| ParamsError::Syntax);
+
+        let s = s.strip_prefix("CubeHash").ok_or(ParamsError::Syntax)?;
+        let (i, s) = s.split_once('+').ok_or(ParamsError::Syntax)?;
+        let (r, s) = s.split_once('/').ok_or(ParamsError::Syntax)?;
+        let (b, s) = s.split_once('+').ok_or(ParamsError::Syntax)?;
+        let (f, h) = s.split_once('-').ok_or(ParamsError::Syntax)?;
+
+        let params = Self { i: parse(i)?, r: parse(r)?, b: parse(b)?, f: parse(f)?, h: parse(h)? };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InitialRounds => "initial rounds must be at least 1",
+            Self::Rounds => "rounds per block must be at least 1",
+            Self::BlockSize => "block size must be between 1 and 128 bytes",
+            Self::FinalRounds => "final rounds must be at least 1",
+            Self::OutputSize => "output size must be a multiple of 8 between 8 and 512 bits",
+            Self::Syntax => "expected parameters of the form CubeHashi+r/b+f-h"
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParamsError {}
+
+#[derive(Clone)]
+pub struct DynCubeHash {
+    params: CubeHashParams,
+    iv: Backend,
+    state: Backend,
+    buffer: [u8; 128],
+    pos: usize
+}
+
+impl DynCubeHash {
+    pub fn with_params(params: CubeHashParams) -> Result<Self, ParamsError> {
+        params.validate()?;
+        let iv = Backend::new(&iv(params.h / 8, params.b, params.r, params.i));
+        Ok(Self { params, state: iv.clone(), iv, buffer: [0; 128], pos: 0 })
+    }
+
+    /// # Panics
+    /// If `params` doesn't pass [`CubeHashParams::validate`].
+    #[cfg(feature = "selectable-backend")]
+    pub fn with_params_and_backend(params: CubeHashParams, backend: BackendSelector) -> Result<Self, BackendError> {
+        if let Err(e) = params.validate() {
+            panic!("invalid CubeHash parameters {params}: {e}");
+        }
+        let iv = Backend::new_with_backend(backend, &iv(params.h / 8, params.b, params.r, params.i))?;
+        Ok(Self { params, state: iv.clone(), iv, buffer: [0; 128], pos: 0 })
+    }
+
+    pub fn backend(&self) -> BackendSelector {
+        self.state.selector()
+    }
+
+    pub fn params(&self) -> CubeHashParams {
+        self.params
+    }
+
+    fn finalize_dirty(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        if out.len() != VariableOutput::output_size(self) {
+            return Err(InvalidBufferSize);
+        }
+
+        let Self { params, state, buffer, pos, .. } = self;
+        let b = params.b.into();
+        buffer[*pos] = 0x80;
+        buffer[*pos + 1..b].fill(0);
+        state.update_blocks([&buffer[..b]], params.r);
+        state.finalize(params.f);
+        state.output(out);
+        Ok(())
+    }
+}
+
+impl HashMarker for DynCubeHash {}
+
+impl fmt::Debug for DynCubeHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dyn{} {{ ... }}", self.params)
+    }
+}
+
+impl Update for DynCubeHash {
+    fn update(&mut self, mut data: &[u8]) {
+        let Self { params, state, buffer, pos, .. } = self;
+        let b = params.b.into();
+
+        if *pos > 0 {
+            let n = data.len().min(b - *pos);
+            buffer[*pos..*pos + n].copy_from_slice(&data[..n]);
+            *pos += n;
+            data = &data[n..];
+            if *pos < b {
+                return;
+            }
+            state.update_blocks([&buffer[..b]], params.r);
+            *pos = 0;
+        }
+
+        let blocks = data.chunks_exact(b);
+        let rem = blocks.remainder();
+        state.update_blocks(blocks, params.r);
+        buffer[..rem.len()].copy_from_slice(rem);
+        *pos = rem.len();
+    }
+}
+
+impl Reset for DynCubeHash {
+    fn reset(&mut self) {
+        self.state = self.iv.clone();
+        self.pos = 0;
+    }
+}
+
+impl VariableOutput for DynCubeHash {
+    const MAX_OUTPUT_SIZE: usize = 64;
+
+    fn new(output_size: usize) -> Result<Self, InvalidOutputSize> {
+        let h = u16::try_from(output_size).ok().and_then(|n| n.checked_mul(8)).ok_or(InvalidOutputSize)?;
+        Self::with_params(CubeHashParams { h, ..Default::default() }).map_err(|_| InvalidOutputSize)
+    }
+
+    fn output_size(&self) -> usize {
+        (self.params.h / 8).into()
+    }
+
+    fn finalize_variable(mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        self.finalize_dirty(out)
+    }
+}
+
+impl VariableOutputReset for DynCubeHash {
+    fn finalize_variable_reset(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        self.finalize_dirty(out)?;
+        Reset::reset(self);
+        Ok(())
+    }
+}
+
+// only with our std feature, which guarantees digest's alloc methods exist
+#[cfg(feature = "std")]
+impl digest::DynDigest for DynCubeHash {
+    fn update(&mut self, data: &[u8]) {
+        Update::update(self, data);
+    }
+
+    fn finalize_into(mut self, buf: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        self.finalize_dirty(buf)
+    }
+
+    fn finalize_into_reset(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        self.finalize_variable_reset(out)
+    }
+
+    fn reset(&mut self) {
+        Reset::reset(self);
+    }
+
+    fn output_size(&self) -> usize {
+        VariableOutput::output_size(self)
+    }
+
+    fn box_clone(&self) -> Box<dyn digest::DynDigest> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for DynCubeHash {
+    fn drop(&mut self) {
+        use digest::zeroize::Zeroize;
+
+        self.iv.zeroize();
+        self.state.zeroize();
+        self.buffer.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl digest::zeroize::ZeroizeOnDrop for DynCubeHash {}
@@ -1,28 +1,79 @@
 #![no_std]
-#![cfg_attr(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"), feature(stdarch_x86_avx512))]
-#![cfg_attr(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "unstable-avx512"), feature(avx512_target_feature))]
+#![cfg_attr(all(any(target_arch = "x86", target_arch = "x86_64"), cubehash_avx512_nightly), feature(stdarch_x86_avx512))]
+#![cfg_attr(all(any(target_arch = "x86", target_arch = "x86_64"), cubehash_avx512_nightly), feature(avx512_target_feature))]
+#![cfg_attr(cubehash_portable, feature(portable_simd))]
+
+#[cfg(feature = "std")]
+extern crate std;
 
 mod cubehash;
 mod cubemac;
+mod dyncubehash;
 
-use digest::{core_api::CoreWrapper, typenum::consts::{U16, U20, U28, U32, U48, U64}};
+use digest::{core_api::{CoreWrapper, RtVariableCoreWrapper}, typenum::consts::{U1, U16, U20, U28, U32, U48, U64}};
 
-pub use digest::{self, Digest, Mac, KeyInit};
+pub use digest::{self, Digest, ExtendableOutput, Mac, KeyInit, XofReader, VariableOutput};
 
-#[cfg(feature = "selectable-backend")]
-pub use cubehash::BackendSelector as CubeHashBackend;
+pub use cubehash::{available_backends, default_backend_override, set_default_backend, BackendError, BackendSelector as CubeHashBackend};
+#[cfg(feature = "std")]
+pub use cubehash::rejected_env_backend;
+#[cfg(feature = "autotune")]
+pub use cubehash::autotune::{autotune, Autotune};
+#[cfg(feature = "self-test")]
+pub use cubehash::selftest::self_test;
 
-pub use cubehash::CubeHashCore;
-type CubeHash<H> = CoreWrapper<CubeHashCore<16, 16, 32, H>>;
+pub use cubehash::{CubeHashCore, CubeHashPermutation, CubeHashReaderCore};
+type CubeHash<H> = CoreWrapper<CubeHashCore<16, 16, U32, 32, H>>;
 pub type CubeHash128 = CubeHash<U16>;
 pub type CubeHash160 = CubeHash<U20>;
 pub type CubeHash224 = CubeHash<U28>;
 pub type CubeHash256 = CubeHash<U32>;
 pub type CubeHash384 = CubeHash<U48>;
 pub type CubeHash512 = CubeHash<U64>;
+pub type CubeHashVar = RtVariableCoreWrapper<CubeHashCore<16, 16, U32, 32, U64>>;
+
+// CubeHash8/1 from round 1, i.e. CubeHash80+8/1+80
+type CubeHash8_1<H> = CoreWrapper<CubeHashCore<80, 8, U1, 80, H>>;
+pub type CubeHash8_1_128 = CubeHash8_1<U16>;
+pub type CubeHash8_1_160 = CubeHash8_1<U20>;
+pub type CubeHash8_1_224 = CubeHash8_1<U28>;
+pub type CubeHash8_1_256 = CubeHash8_1<U32>;
+pub type CubeHash8_1_384 = CubeHash8_1<U48>;
+pub type CubeHash8_1_512 = CubeHash8_1<U64>;
+
+// CubeHash16/32 from round 2, i.e. the "formal" CubeHash160+16/32+160
+type CubeHash16_32<H> = CoreWrapper<CubeHashCore<160, 16, U32, 160, H>>;
+pub type CubeHash16_32_128 = CubeHash16_32<U16>;
+pub type CubeHash16_32_160 = CubeHash16_32<U20>;
+pub type CubeHash16_32_224 = CubeHash16_32<U28>;
+pub type CubeHash16_32_256 = CubeHash16_32<U32>;
+pub type CubeHash16_32_384 = CubeHash16_32<U48>;
+pub type CubeHash16_32_512 = CubeHash16_32<U64>;
 
 pub use cubemac::CubeMacCore;
-pub type CubeMac128 = CoreWrapper<CubeMacCore<16, 16, 32, U16>>;
+type CubeMac<H> = CoreWrapper<CubeMacCore<16, 16, U32, 32, H>>;
+pub type CubeMac128 = CubeMac<U16>;
+pub type CubeMac160 = CubeMac<U20>;
+pub type CubeMac224 = CubeMac<U28>;
+pub type CubeMac256 = CubeMac<U32>;
+pub type CubeMac384 = CubeMac<U48>;
+pub type CubeMac512 = CubeMac<U64>;
+
+pub use dyncubehash::{DynCubeHash, CubeHashParams, ParamsError};
+
+// CubeHash16+16/32+32 evaluable at compile time; H is in bytes
+pub const fn cubehash<const H: usize>(data: &[u8]) -> [u8; H] {
+    const { assert!(H > 0 && H <= 64, "CubeHash output size must be between 1 and 64 bytes") };
+    cubehash::const_digest::<H>(16, 16, 32, 32, data)
+}
+
+pub const fn cubehash256(data: &[u8]) -> [u8; 32] {
+    cubehash(data)
+}
+
+pub const fn cubehash512(data: &[u8]) -> [u8; 64] {
+    cubehash(data)
+}
 
 #[cfg(test)]
 mod test {
@@ -36,7 +87,7 @@ mod test {
 
         let cmac = {
             let mut h = CubeHash128::new();
-            h.update(&k);
+            h.update(k);
             h.update(&d);
             h.finalize()
         };
@@ -45,4 +96,300 @@ mod test {
         m.update(&d);
         m.verify(&cmac).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cubemac_many_consistent() {
+        let k = [10; 64];
+        let d: alloc::vec::Vec<u8> = (0..1000).map(|x| x as u8).collect();
+        let inputs: alloc::vec::Vec<&[u8]> = (0..40).map(|i| &d[..i * 23]).collect();
+
+        let mut tags = alloc::vec![Default::default(); inputs.len()];
+        CubeMacCore::<16, 16, U32, 32, U16>::new(&k.into()).mac_many(&inputs, &mut tags);
+
+        for (input, tag) in core::iter::zip(&inputs, &tags) {
+            let mut m = CubeMac128::new_from_slice(&k).unwrap();
+            m.update(input);
+            m.verify(tag).unwrap();
+        }
+    }
+
+    #[test]
+    fn cubemac_reset() {
+        let k = [10; 64];
+        let mut m = CubeMac128::new_from_slice(&k).unwrap();
+
+        for msg in [&b"first"[..], b"", &[69; 1000]] {
+            let tag = CubeHash128::new().chain_update(k).chain_update(msg).finalize();
+            m.update(msg);
+            assert_eq!(m.finalize_reset().into_bytes(), tag);
+        }
+
+        m.update(b"garbage");
+        Mac::reset(&mut m);
+        m.update(b"abc");
+        m.verify(&CubeHash128::new().chain_update(k).chain_update(b"abc").finalize()).unwrap();
+    }
+
+    #[test]
+    fn cubemac_serialization() {
+        use digest::crypto_common::hazmat::SerializableState;
+
+        let k = [10; 64];
+        let d: alloc::vec::Vec<u8> = (0..1000).map(|x| x as u8).collect();
+        let tag = CubeMac128::new_from_slice(&k).unwrap().chain_update(&d).finalize().into_bytes();
+
+        let state = CubeMac128::new_from_slice(&k).unwrap().chain_update(&d[..333]).serialize();
+        let mut m = CubeMac128::deserialize(&state).unwrap();
+        m.update(&d[333..]);
+        assert_eq!(m.finalize_reset().into_bytes(), tag);
+
+        // still keyed after a restore
+        m.update(&d);
+        m.verify(&tag).unwrap();
+    }
+
+    #[test]
+    fn cubemac_keyed_hash() {
+        let k: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let d = b"The quick brown fox jumps over the lazy dog";
+
+        CubeMac256::new_from_slice(&k).unwrap().chain_update(d)
+            .verify(&CubeHash256::new().chain_update(k).chain_update(d).finalize()).unwrap();
+        CubeMac512::new_from_slice(&k).unwrap().chain_update(d)
+            .verify(&CubeHash512::new().chain_update(k).chain_update(d).finalize()).unwrap();
+        CoreWrapper::<CubeMacCore<160, 16, U32, 160, U48>>::new_from_slice(&k).unwrap().chain_update(d)
+            .verify(&CubeHash16_32_384::new().chain_update(k).chain_update(d).finalize()).unwrap();
+        CoreWrapper::<CubeMacCore<80, 8, U1, 80, U64>>::new_from_slice(&k).unwrap().chain_update(d)
+            .verify(&CubeHash8_1_512::new().chain_update(k).chain_update(d).finalize()).unwrap();
+        assert_eq!(
+            CubeMac512::new(&k.into()).chain_update(d).finalize(),
+            CubeMac512::new_from_slice(&k).unwrap().chain_update(d).finalize()
+        );
+    }
+
+    #[test]
+    fn cubemac_key_lengths() {
+        let k: [u8; 130] = core::array::from_fn(|i| i as u8 ^ 0x3c);
+        let d = b"abc";
+
+        // 10* padded to whole blocks, with a zero block added when that
+        // would be as long as a 64-byte key
+        let padded = |key: &[u8]| {
+            let mut p = key.to_vec();
+            p.push(0x80);
+            p.resize(p.len().next_multiple_of(32), 0);
+            if p.len() == 64 {
+                p.resize(96, 0);
+            }
+            p
+        };
+        for len in [0, 1, 31, 32, 33, 63, 65, 95, 96, 130] {
+            CubeMac256::new_from_slice(&k[..len]).unwrap().chain_update(d)
+                .verify(&CubeHash256::new().chain_update(padded(&k[..len])).chain_update(d).finalize()).unwrap();
+        }
+
+        let mut tags: alloc::vec::Vec<_> = (0..=k.len())
+            .map(|len| CubeMac256::new_from_slice(&k[..len]).unwrap().chain_update(d).finalize().into_bytes())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        assert_eq!(tags.len(), k.len() + 1);
+    }
+
+    #[cfg(feature = "selectable-backend")]
+    #[test]
+    fn cubemac_backends_consistent() {
+        let k = [10; 64];
+        let d: alloc::vec::Vec<u8> = (0..1000).map(|x| x as u8).collect();
+
+        let tag = CubeMac128::new_from_slice(&k).unwrap().chain_update(&d).finalize().into_bytes();
+
+        for backend in available_backends() {
+            let core = CubeMacCore::<16, 16, U32, 32, U16>::new_with_backend(&k, backend).unwrap();
+            assert_eq!(core.backend(), backend);
+            let mut m = CubeMac128::from_core(core);
+            m.update(&d);
+            m.verify(&tag).unwrap();
+        }
+    }
+
+    fn dyn_digest(params: &str, data: &[u8]) -> alloc::vec::Vec<u8> {
+        let mut h = DynCubeHash::with_params(params.parse().unwrap()).unwrap();
+        let mut out = alloc::vec![0; h.output_size()];
+        for chunk in data.chunks(7) {
+            digest::Update::update(&mut h, chunk);
+        }
+        h.finalize_variable(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn dyncubehash_consistent() {
+        let d = alloc::vec![69; 100000];
+
+        assert_eq!(dyn_digest("CubeHash16+16/32+32-256", &d), CubeHash256::digest(&d)[..]);
+        assert_eq!(dyn_digest("CubeHash16+16/32+32-512", &d), CubeHash512::digest(&d)[..]);
+        assert_eq!(
+            dyn_digest("CubeHash16+16/17+32-448", &d),
+            CoreWrapper::<CubeHashCore<16, 16, digest::typenum::U17, 32, digest::typenum::U56>>::digest(&d)[..]
+        );
+    }
+
+    #[test]
+    fn dyncubehash_kat() {
+        for (i, r, b, f, msg, expected) in cubehash::kat::KATS.into_iter().chain(cubehash::kat::COMPUTED) {
+            let params = CubeHashParams { i, r, b, f, h: expected.len() as u16 * 8 };
+            assert_eq!(dyn_digest(&alloc::format!("{params}"), msg), expected, "{params}");
+        }
+    }
+
+    #[cfg(feature = "selectable-backend")]
+    #[test]
+    fn dyncubehash_backends_consistent() {
+        let d: alloc::vec::Vec<u8> = (0..1000).map(|x| x as u8).collect();
+        let params: CubeHashParams = "CubeHash16+16/17+32-448".parse().unwrap();
+        let expected = dyn_digest("CubeHash16+16/17+32-448", &d);
+
+        for backend in available_backends() {
+            let mut h = DynCubeHash::with_params_and_backend(params, backend).unwrap();
+            assert_eq!(h.backend(), backend);
+            let mut out = [0; 56];
+            for chunk in d.chunks(7) {
+                digest::Update::update(&mut h, chunk);
+            }
+            h.finalize_variable(&mut out).unwrap();
+            assert_eq!(out[..], expected, "{backend}");
+        }
+        #[cfg(cubehash_sse2)]
+        assert!(matches!(DynCubeHash::with_params_and_backend(params, CubeHashBackend::Neon), Err(BackendError::NotCompiledIn)));
+    }
+
+    #[test]
+    fn dyncubehash_reset() {
+        let mut h = DynCubeHash::new(32).unwrap();
+        let mut a = [0; 32];
+        let mut b = [0; 32];
+        digest::Update::update(&mut h, b"garbage");
+        digest::VariableOutputReset::finalize_variable_reset(&mut h, &mut a).unwrap();
+        digest::Update::update(&mut h, b"abc");
+        h.finalize_variable(&mut b).unwrap();
+        assert_eq!(b, CubeHash256::digest(b"abc")[..]);
+    }
+
+    #[test]
+    fn variable_output_consistent() {
+        let d = alloc::vec![69; 10000];
+
+        for n in 1..=64 {
+            let mut h = CubeHashVar::new(n).unwrap();
+            let mut out = alloc::vec![0; n];
+            digest::Update::update(&mut h, &d);
+            h.finalize_variable(&mut out).unwrap();
+            assert_eq!(out, dyn_digest(&alloc::format!("CubeHash16+16/32+32-{}", n * 8), &d));
+        }
+
+        let var_digest = |n| {
+            let mut out = alloc::vec![0; n];
+            digest::Update::chain(CubeHashVar::new(n).unwrap(), &d).finalize_variable(&mut out).unwrap();
+            out
+        };
+        assert_eq!(var_digest(16), CubeHash128::digest(&d)[..]);
+        assert_eq!(var_digest(20), CubeHash160::digest(&d)[..]);
+        assert_eq!(var_digest(28), CubeHash224::digest(&d)[..]);
+        assert_eq!(var_digest(32), CubeHash256::digest(&d)[..]);
+        assert_eq!(var_digest(48), CubeHash384::digest(&d)[..]);
+        assert_eq!(var_digest(64), CubeHash512::digest(&d)[..]);
+        assert!(CubeHashVar::new(0).is_err());
+        assert!(CubeHashVar::new(65).is_err());
+    }
+
+    #[test]
+    fn variable_output_reset() {
+        use digest::{crypto_common::hazmat::SerializableState, VariableOutputReset};
+
+        // reset has to go back to the IV for 32 bytes, not the type's 64
+        let mut h = CubeHashVar::new(32).unwrap();
+        for msg in [&b"abc"[..], b"abc", &[69; 1000]] {
+            let mut out = [0; 32];
+            digest::Update::update(&mut h, msg);
+            h.finalize_variable_reset(&mut out).unwrap();
+            assert_eq!(out, CubeHash256::digest(msg)[..]);
+        }
+
+        // including after a restore
+        let mut h = CubeHashVar::deserialize(&digest::Update::chain(CubeHashVar::new(20).unwrap(), b"garbage").serialize()).unwrap();
+        digest::Reset::reset(&mut h);
+        let mut out = [0; 20];
+        digest::Update::chain(h, b"abc").finalize_variable(&mut out).unwrap();
+        assert_eq!(out, CubeHash160::digest(b"abc")[..]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn dyncubehash_dyn_digest() {
+        let mut h: alloc::boxed::Box<dyn digest::DynDigest> = alloc::boxed::Box::new(DynCubeHash::new(64).unwrap());
+        h.update(b"abc");
+        let h2 = h.box_clone();
+        assert_eq!(h.finalize()[..], CubeHash512::digest(b"abc")[..]);
+        assert_eq!(h2.finalize()[..], CubeHash512::digest(b"abc")[..]);
+    }
+
+    #[test]
+    fn presets_kat() {
+        let mut ran = 0;
+        for (i, r, b, f, msg, expected) in cubehash::kat::KATS.into_iter().chain(cubehash::kat::COMPUTED) {
+            let out = match (i, r, b, f, expected.len()) {
+                (16, 16, 32, 32, 16) => CubeHash128::digest(msg).to_vec(),
+                (16, 16, 32, 32, 20) => CubeHash160::digest(msg).to_vec(),
+                (16, 16, 32, 32, 28) => CubeHash224::digest(msg).to_vec(),
+                (16, 16, 32, 32, 32) => CubeHash256::digest(msg).to_vec(),
+                (16, 16, 32, 32, 48) => CubeHash384::digest(msg).to_vec(),
+                (16, 16, 32, 32, 64) => CubeHash512::digest(msg).to_vec(),
+                (80, 8, 1, 80, 64) => CubeHash8_1_512::digest(msg).to_vec(),
+                (160, 16, 32, 160, 32) => CubeHash16_32_256::digest(msg).to_vec(),
+                (160, 16, 32, 160, 64) => CubeHash16_32_512::digest(msg).to_vec(),
+                _ => continue
+            };
+            assert_eq!(out, expected, "{i}+{r}/{b}+{f}-{}", expected.len() * 8);
+            ran += 1;
+        }
+        assert_eq!(ran, 16);
+    }
+
+    #[test]
+    fn algorithm_names() {
+        assert_eq!(alloc::format!("{:?}", CubeHash256::new()), "CubeHash16+16/32+32-256 { .. }");
+        assert_eq!(alloc::format!("{:?}", CubeHash8_1_224::new()), "CubeHash80+8/1+80-224 { .. }");
+        assert_eq!(alloc::format!("{:?}", CubeHash16_32_384::new()), "CubeHash160+16/32+160-384 { .. }");
+        assert_eq!(alloc::format!("{:?}", CubeMac128::new_from_slice(&[0; 64]).unwrap()), "CubeMac16+16/32+32-128 { .. }");
+    }
+
+    #[test]
+    fn const_consistent() {
+        const ID: [u8; 32] = cubehash256(b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(ID, CubeHash256::digest(b"The quick brown fox jumps over the lazy dog")[..]);
+
+        let d: alloc::vec::Vec<u8> = (0..300).map(|x| x as u8).collect();
+        for n in [0, 1, 31, 32, 33, 64, 255, 300] {
+            assert_eq!(cubehash256(&d[..n]), CubeHash256::digest(&d[..n])[..]);
+            assert_eq!(cubehash512(&d[..n]), CubeHash512::digest(&d[..n])[..]);
+            assert_eq!(cubehash::<20>(&d[..n]), CubeHash160::digest(&d[..n])[..]);
+        }
+    }
+
+    #[test]
+    fn params_parse() {
+        for s in ["CubeHash16+16/32+32-512", "CubeHash160+16/32+160-256", "CubeHash80+8/1+80-8"] {
+            assert_eq!(alloc::format!("{}", s.parse::<CubeHashParams>().unwrap()), s);
+        }
+        assert_eq!("CubeHash0+16/32+32-512".parse::<CubeHashParams>(), Err(ParamsError::InitialRounds));
+        assert_eq!("CubeHash16+0/32+32-512".parse::<CubeHashParams>(), Err(ParamsError::Rounds));
+        assert_eq!("CubeHash16+16/129+32-512".parse::<CubeHashParams>(), Err(ParamsError::BlockSize));
+        assert_eq!("CubeHash16+16/32+0-512".parse::<CubeHashParams>(), Err(ParamsError::FinalRounds));
+        assert_eq!("CubeHash16+16/32+32-513".parse::<CubeHashParams>(), Err(ParamsError::OutputSize));
+        assert_eq!("CubeHash16+16/32+32-1024".parse::<CubeHashParams>(), Err(ParamsError::OutputSize));
+        assert_eq!("CubeHash16+16/32-512".parse::<CubeHashParams>(), Err(ParamsError::Syntax));
+        assert!(DynCubeHash::new(65).is_err());
+        assert!(DynCubeHash::new(0).is_err());
+    }
+}